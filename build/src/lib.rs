@@ -0,0 +1,378 @@
+#![allow(clippy::needless_return)]
+
+//! Builds Doggo packages from a Cargo build script, like the `cc` crate does for single files.
+//!
+//! In `build.rs`:
+//!
+//! ```no_run
+//! doggo_build::Build::new("native").compile();
+//! ```
+//!
+//! The package gets built for `$TARGET`, with the `release` profile when `$PROFILE` is
+//! `release` and `dev` otherwise, and everything ends up in `$OUT_DIR`. Cargo is told to
+//! link the built libraries and what they need, and to rerun the build script when
+//! anything the build read changes.
+
+use std::{
+    collections::BTreeSet,
+    env, io,
+    path::{Path, PathBuf},
+};
+
+use doggo_core::{
+    build::{BuildConfig, BuildError, build},
+    compiler_backend::{CompilerKind, CppStdlib},
+    features::FeatureSelection,
+    manifest::{Manifest, PackageKind},
+    profile::Profile,
+    project::{Workspace, WorkspaceError},
+    scheduler::default_job_count,
+    target::{ObjectFormat, Target, TargetError},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Workspace(WorkspaceError),
+    #[error("{0}")]
+    Build(BuildError),
+    #[error("{0}")]
+    Io(io::Error),
+    #[error("{0}")]
+    Target(TargetError),
+    #[error("No Doggo.toml found in {0} or any of its parents.")]
+    NoManifest(PathBuf),
+    #[error("Environment variable {0} isn't set, is this running in a build script?")]
+    MissingEnv(&'static str),
+    #[error("{0}.")]
+    CompilerNotFound(String),
+    #[error("Package {0} is an executable, only libraries can be linked into Rust.")]
+    NotLibrary(String),
+}
+
+impl From<WorkspaceError> for Error {
+    fn from(value: WorkspaceError) -> Self {
+        return Self::Workspace(value);
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(value: BuildError) -> Self {
+        return Self::Build(value);
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        return Self::Io(value);
+    }
+}
+
+impl From<TargetError> for Error {
+    fn from(value: TargetError) -> Self {
+        return Self::Target(value);
+    }
+}
+
+/// A build of one or more packages of a Doggo workspace. Everything not set here
+/// comes from the environment Cargo runs build scripts in.
+#[derive(Debug, Clone)]
+pub struct Build {
+    path: PathBuf,
+    packages: Vec<String>,
+    profile: Option<String>,
+    target: Option<Target>,
+    compiler: Option<CompilerKind>,
+    features: FeatureSelection,
+    jobs: Option<usize>,
+    out_dir: Option<PathBuf>,
+    cargo_metadata: bool,
+}
+
+impl Build {
+    /// `path` is the directory of the package's Doggo.toml, or of a workspace's.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        return Self {
+            path: path.as_ref().to_path_buf(),
+            packages: vec![],
+            profile: None,
+            target: None,
+            compiler: None,
+            features: FeatureSelection::default(),
+            jobs: None,
+            out_dir: None,
+            cargo_metadata: true,
+        };
+    }
+
+    /// Builds a member of the workspace, instead of the package at the path or the
+    /// default members. Can be called more than once.
+    pub fn package(&mut self, name: &str) -> &mut Self {
+        self.packages.push(name.to_string());
+
+        return self;
+    }
+
+    /// Overrides the profile picked from `$PROFILE`.
+    pub fn profile(&mut self, name: &str) -> &mut Self {
+        self.profile = Some(name.to_string());
+
+        return self;
+    }
+
+    /// Overrides `$TARGET`.
+    pub fn target(&mut self, target: Target) -> &mut Self {
+        self.target = Some(target);
+
+        return self;
+    }
+
+    /// Overrides `build.compiler` of the workspace.
+    pub fn compiler(&mut self, compiler: CompilerKind) -> &mut Self {
+        self.compiler = Some(compiler);
+
+        return self;
+    }
+
+    /// Either `<feature>` or `<package>/<feature>`, like `--features`.
+    pub fn feature(&mut self, feature: &str) -> &mut Self {
+        self.features.features.push(feature.to_string());
+
+        return self;
+    }
+
+    pub fn all_features(&mut self) -> &mut Self {
+        self.features.all_features = true;
+
+        return self;
+    }
+
+    pub fn no_default_features(&mut self) -> &mut Self {
+        self.features.no_default_features = true;
+
+        return self;
+    }
+
+    /// Defaults to `$NUM_JOBS`, then the number of CPUs.
+    pub fn jobs(&mut self, jobs: usize) -> &mut Self {
+        self.jobs = Some(jobs);
+
+        return self;
+    }
+
+    /// Overrides `$OUT_DIR`. The build goes in a `doggo` directory in it.
+    pub fn out_dir(&mut self, dir: impl AsRef<Path>) -> &mut Self {
+        self.out_dir = Some(dir.as_ref().to_path_buf());
+
+        return self;
+    }
+
+    /// Whether to print the `cargo:` lines, on by default.
+    pub fn cargo_metadata(&mut self, enabled: bool) -> &mut Self {
+        self.cargo_metadata = enabled;
+
+        return self;
+    }
+
+    /// Like [`Build::try_compile`], but panics on errors, which is what build scripts want.
+    pub fn compile(&self) -> Vec<PathBuf> {
+        return match self.try_compile() {
+            Ok(outputs) => outputs,
+            Err(e) => panic!("Failed to build Doggo package: {e}"),
+        };
+    }
+
+    /// Builds the packages, and returns their outputs.
+    pub fn try_compile(&self) -> Result<Vec<PathBuf>, Error> {
+        let out_dir = match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => env_path("OUT_DIR")?,
+        };
+
+        let target = match &self.target {
+            Some(target) => target.clone(),
+            None => match env::var("TARGET") {
+                Ok(triple) => Target::parse(&triple)?,
+                Err(_) => Target::host(),
+            },
+        };
+
+        let profile = match &self.profile {
+            Some(profile) => profile.clone(),
+            None if env::var("PROFILE").is_ok_and(|it| it == "release") => {
+                Profile::RELEASE.to_string()
+            }
+            None => Profile::DEV.to_string(),
+        };
+
+        let jobs = self
+            .jobs
+            .or_else(|| env::var("NUM_JOBS").ok()?.parse().ok())
+            .unwrap_or_else(default_job_count);
+
+        let mut workspace = Workspace::load(self.path.clone(), Some(&target))?
+            .ok_or_else(|| Error::NoManifest(self.path.clone()))?;
+
+        let roots = workspace.select_members(&self.packages, false)?;
+
+        for root in &roots {
+            let package = workspace.package(*root);
+
+            if package.output == PackageKind::Executable {
+                return Err(Error::NotLibrary(package.name.get().to_string()));
+            }
+        }
+
+        workspace.resolve_features(&roots, &self.features)?;
+
+        let config = BuildConfig {
+            profile: workspace.profile(&profile)?,
+            target,
+            build_directory: out_dir.join("doggo"),
+        };
+
+        let compiler = self
+            .compiler
+            .unwrap_or(workspace.compiler)
+            .backend(&config.target)
+            .map_err(|e| Error::CompilerNotFound(e.to_string()))?;
+
+        let output = build(&workspace, compiler.as_ref(), &config, &roots, jobs)?;
+
+        let outputs = roots
+            .iter()
+            .map(|it| output.outputs[it].clone())
+            .collect::<Vec<_>>();
+
+        if !self.cargo_metadata {
+            return Ok(outputs);
+        }
+
+        let mut lines = BTreeSet::new();
+
+        let mut link = vec![];
+
+        // Rust crates the packages depend on would bring a second copy of std. Those
+        // should be dependencies of the Rust crate instead.
+        let rust_libraries = workspace
+            .dependency_order(&roots)?
+            .into_iter()
+            .flat_map(|it| workspace.cargo_dependencies(it))
+            .map(|it| it.link_name(&config.target))
+            .collect::<Vec<_>>();
+
+        for root in &roots {
+            let package = workspace.package(*root);
+            let path = &output.outputs[root];
+
+            lines.insert(format!(
+                "cargo:rustc-link-search=native={}",
+                path.parent().unwrap().display()
+            ));
+
+            let kind = match package.output {
+                PackageKind::DynamicLibrary => "dylib",
+                _ => "static",
+            };
+
+            link.push(format!(
+                "cargo:rustc-link-lib={kind}={}",
+                package.name.get()
+            ));
+
+            // Dynamic libraries already link everything they need.
+            if package.output != PackageKind::StaticLibrary {
+                continue;
+            }
+
+            let libraries = &output.libraries[root];
+
+            for lib_path in &libraries.lib_paths {
+                lines.insert(format!("cargo:rustc-link-search=native={lib_path}"));
+            }
+
+            for library in &libraries.static_libs {
+                link.push(format!("cargo:rustc-link-lib=static={library}"));
+            }
+
+            for library in &libraries.dynamic_libs {
+                if !rust_libraries.contains(library) {
+                    link.push(format!("cargo:rustc-link-lib={library}"));
+                }
+            }
+
+            if libraries.cpp
+                && let Some(stdlib) = cpp_library(&workspace, &config.target)
+            {
+                link.push(format!("cargo:rustc-link-lib={stdlib}"));
+            }
+        }
+
+        let mut rerun = BTreeSet::new();
+
+        rerun.insert(workspace.path.join(Manifest::TOML_NAME));
+
+        for index in workspace.dependency_order(&roots)? {
+            let package = workspace.package(index);
+
+            rerun.insert(package.path.join(Manifest::TOML_NAME));
+            rerun.insert(package.path.join("src"));
+
+            for directory in package
+                .public_include
+                .iter()
+                .chain(&package.private_include)
+            {
+                rerun.insert(directory.clone());
+            }
+        }
+
+        rerun.extend(output.inputs()?.into_iter().map(PathBuf::from));
+
+        for line in lines {
+            println!("{line}");
+        }
+
+        let mut printed = vec![];
+
+        // Order matters for static libraries, so these only get deduplicated.
+        for line in link {
+            if !printed.contains(&line) {
+                println!("{line}");
+                printed.push(line);
+            }
+        }
+
+        for path in rerun {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+
+        return Ok(outputs);
+    }
+}
+
+fn env_path(name: &'static str) -> Result<PathBuf, Error> {
+    return env::var_os(name)
+        .map(PathBuf::from)
+        .ok_or(Error::MissingEnv(name));
+}
+
+/// The C++ standard library to link, since rustc links with the C driver.
+fn cpp_library(workspace: &Workspace, target: &Target) -> Option<&'static str> {
+    if target.is_msvc() {
+        return None;
+    }
+
+    let stdlib = workspace
+        .cpp_stdlib
+        .unwrap_or(if target.object_format == ObjectFormat::MachO {
+            CppStdlib::LibCpp
+        } else {
+            CppStdlib::LibStdCpp
+        });
+
+    return Some(match stdlib {
+        CppStdlib::LibCpp => "c++",
+        CppStdlib::LibStdCpp => "stdc++",
+    });
+}
@@ -0,0 +1,417 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use doggo_core::manifest::{Manifest, ManifestKind, validate_package_name};
+
+use crate::{CliError, ProjectInit};
+
+const MANIFEST_NAME: &str = "Doggo.toml";
+
+/// Turns a package name into something usable as a C identifier.
+fn identifier(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|it| if it.is_ascii_alphanumeric() { it } else { '_' })
+        .collect();
+
+    if ident.starts_with(|it: char| it.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+
+    return ident;
+}
+
+fn manifest_text(name: &str, kind: ProjectInit) -> String {
+    let output = match kind {
+        ProjectInit::Binary => "executable",
+        ProjectInit::Staticlib => "static_library",
+        ProjectInit::Dylib => "dynamic_library",
+    };
+
+    let mut package = toml_edit::Table::new();
+
+    package["name"] = toml_edit::value(name);
+    package["output"] = toml_edit::value(output);
+
+    let mut document = toml_edit::DocumentMut::new();
+
+    document["package"] = toml_edit::Item::Table(package);
+
+    return document.to_string();
+}
+
+fn write_sources(path: &Path, name: &str, kind: ProjectInit) -> std::io::Result<()> {
+    let src = path.join("src");
+
+    fs::create_dir_all(&src)?;
+
+    if kind == ProjectInit::Binary {
+        let main = src.join("main.c");
+
+        if !main.exists() {
+            fs::write(
+                main,
+                "#include <stdio.h>\n\nint main() {\n    printf(\"Hello, world!\\n\");\n\n    return 0;\n}\n",
+            )?;
+        }
+
+        return Ok(());
+    }
+
+    let ident = identifier(name);
+    let guard = format!("{}_H", ident.to_uppercase());
+
+    let include = path.join("include");
+
+    fs::create_dir_all(&include)?;
+
+    let header = include.join(format!("{name}.h"));
+
+    if !header.exists() {
+        fs::write(
+            header,
+            format!(
+                "#ifndef {guard}\n#define {guard}\n\nint {ident}_add(int a, int b);\n\n#endif\n"
+            ),
+        )?;
+    }
+
+    let source = src.join("lib.c");
+
+    if !source.exists() {
+        fs::write(
+            source,
+            format!(
+                "#include \"{name}.h\"\n\nint {ident}_add(int a, int b) {{\n    return a + b;\n}}\n"
+            ),
+        )?;
+    }
+
+    return Ok(());
+}
+
+fn write_gitignore(path: &Path) -> std::io::Result<()> {
+    let gitignore = path.join(".gitignore");
+
+    if !gitignore.exists() {
+        return fs::write(gitignore, ".doggo\n");
+    }
+
+    let mut content = fs::read_to_string(&gitignore)?;
+
+    if content
+        .lines()
+        .any(|it| it.trim().trim_matches('/') == ".doggo")
+    {
+        return Ok(());
+    }
+
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+
+    content.push_str(".doggo\n");
+
+    return fs::write(gitignore, content);
+}
+
+/// Walks up from the parent of `path`, looking for a workspace manifest.
+fn find_workspace(path: &Path) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    let mut current = path.to_path_buf();
+
+    while current.pop() {
+        if let Some(manifest) = Manifest::load(&current)?
+            && let ManifestKind::Workspace(_) = manifest.kind
+        {
+            return Ok(Some(current));
+        }
+    }
+
+    return Ok(None);
+}
+
+/// Adds the member to the workspace manifest, keeping the rest of the file as it was.
+/// Returns false if it was already listed.
+fn add_workspace_member(
+    workspace: &Path,
+    member: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let manifest_path = workspace.join(MANIFEST_NAME);
+
+    let mut document = read_workspace_manifest(workspace)?;
+
+    let workspace_table = document["workspace"]
+        .as_table_mut()
+        .ok_or_else(|| CliError::MalformedWorkspace(manifest_path.clone()))?;
+
+    let members = workspace_table
+        .entry("members")
+        .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+        .as_array_mut()
+        .ok_or_else(|| CliError::MalformedWorkspace(manifest_path.clone()))?;
+
+    if members.iter().any(|it| it.as_str() == Some(member)) {
+        return Ok(false);
+    }
+
+    push_member(members, member);
+
+    fs::write(manifest_path, document.to_string())?;
+
+    return Ok(true);
+}
+
+/// The workspace manifest, as long as it has somewhere to add members to.
+fn read_workspace_manifest(
+    workspace: &Path,
+) -> Result<toml_edit::DocumentMut, Box<dyn std::error::Error>> {
+    let manifest_path = workspace.join(MANIFEST_NAME);
+
+    let document: toml_edit::DocumentMut = fs::read_to_string(&manifest_path)?.parse()?;
+
+    let members = document
+        .get("workspace")
+        .and_then(|it| it.as_table())
+        .map(|it| it.get("members").map(|it| it.is_array()));
+
+    if !matches!(members, Some(None | Some(true))) {
+        return Err(CliError::MalformedWorkspace(manifest_path).into());
+    }
+
+    return Ok(document);
+}
+
+/// Pushes `member`, formatted like the last element, so multi-line lists stay multi-line.
+/// Comments stay next to the element they were written after.
+fn push_member(members: &mut toml_edit::Array, member: &str) {
+    let mut value = toml_edit::Value::from(member);
+
+    let trailing_comma = members.trailing_comma();
+
+    let Some(last) = members.iter_mut().last() else {
+        members.push(member);
+
+        return;
+    };
+
+    let raw = |it: Option<&toml_edit::RawString>| {
+        return it
+            .and_then(|it| it.as_str())
+            .unwrap_or_default()
+            .to_string();
+    };
+
+    // Only the indent, the rest of the prefix belongs to the element before it.
+    let last_prefix = raw(last.decor().prefix());
+    let indent = last_prefix
+        .rfind('\n')
+        .map(|index| &last_prefix[index + 1..]);
+
+    // Everything between the last element and the `]`. A comment in there is on the
+    // last element's line, so it stays before the new one.
+    let tail = if trailing_comma {
+        raw(Some(members.trailing()))
+    } else {
+        let suffix = raw(last.decor().suffix());
+
+        last.decor_mut().set_suffix("");
+
+        suffix
+    };
+
+    let (line_end, before_bracket) = match tail.rfind('\n') {
+        Some(index) => tail.split_at(index + 1),
+        None => ("", tail.as_str()),
+    };
+
+    let prefix = match (line_end, &indent) {
+        ("", Some(indent)) => format!("\n{indent}"),
+        ("", None) => " ".to_string(),
+        (line_end, indent) => format!("{line_end}{}", indent.unwrap_or_default()),
+    };
+
+    let tail = if line_end.is_empty() {
+        before_bracket.to_string()
+    } else {
+        format!("\n{before_bracket}")
+    };
+
+    value.decor_mut().set_prefix(prefix);
+
+    if trailing_comma {
+        members.set_trailing(tail);
+    } else {
+        value.decor_mut().set_suffix(tail);
+    }
+
+    members.push_formatted(value);
+}
+
+pub fn init_package(path: &Path, kind: ProjectInit) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(path)?;
+
+    let path = path.canonicalize()?;
+
+    if path.join(MANIFEST_NAME).exists() {
+        return Err(CliError::ManifestExists(path.join(MANIFEST_NAME)).into());
+    }
+
+    let name = path
+        .file_name()
+        .and_then(|it| it.to_str())
+        .filter(|it| validate_package_name(it).is_ok())
+        .ok_or_else(|| CliError::InvalidPackageName(path.clone()))?
+        .to_string();
+
+    // Anything wrong with the workspace should stop us before we write something.
+    let workspace = match find_workspace(&path)? {
+        Some(workspace) => {
+            read_workspace_manifest(&workspace)?;
+
+            let member = path
+                .strip_prefix(&workspace)?
+                .components()
+                .map(|it| it.as_os_str().to_str().unwrap())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            Some((workspace, member))
+        }
+        None => None,
+    };
+
+    fs::write(path.join(MANIFEST_NAME), manifest_text(&name, kind))?;
+
+    write_sources(&path, &name, kind)?;
+
+    write_gitignore(&path)?;
+
+    if let Some((workspace, member)) = workspace
+        && add_workspace_member(&workspace, &member)?
+    {
+        println!(
+            "Added {} to workspace ({})",
+            member,
+            workspace.to_str().unwrap()
+        );
+    }
+
+    println!("Created {:?} package {}", kind, name);
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_member(text: &str, member: &str) -> String {
+        let mut document: toml_edit::DocumentMut = text.parse().unwrap();
+
+        push_member(
+            document["workspace"]["members"].as_array_mut().unwrap(),
+            member,
+        );
+
+        return document.to_string();
+    }
+
+    #[test]
+    fn adds_members_like_the_existing_ones() {
+        for (before, after) in [
+            ("[]", r#"["new"]"#),
+            (r#"["a"]"#, r#"["a", "new"]"#),
+            (r#"["a", "b"] # members"#, r#"["a", "b", "new"] # members"#),
+            (
+                "[\n    \"a\",\n    \"b\",\n]",
+                "[\n    \"a\",\n    \"b\",\n    \"new\",\n]",
+            ),
+            (
+                "[\n    \"a\",\n    \"b\"\n]",
+                "[\n    \"a\",\n    \"b\",\n    \"new\"\n]",
+            ),
+            (
+                "[\n  \"a\", # main\n  \"b\", # lib\n] # all of them",
+                "[\n  \"a\", # main\n  \"b\", # lib\n  \"new\",\n] # all of them",
+            ),
+            (
+                "[\n  \"a\", # main\n  \"b\" # lib\n]",
+                "[\n  \"a\", # main\n  \"b\", # lib\n  \"new\"\n]",
+            ),
+            (
+                "[\n  \"a\",\n  # the rest\n  \"b\",\n  ]",
+                "[\n  \"a\",\n  # the rest\n  \"b\",\n  \"new\",\n  ]",
+            ),
+        ] {
+            let text = format!("[workspace]\nmembers = {before}\n");
+
+            assert_eq!(
+                add_member(&text, "new"),
+                format!("[workspace]\nmembers = {after}\n"),
+                "{before}"
+            );
+        }
+    }
+
+    /// An empty directory to init packages in, with a workspace manifest at the root.
+    fn workspace(name: &str, manifest: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("doggo-init-{}-{name}", std::process::id()));
+
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join(MANIFEST_NAME), manifest).unwrap();
+
+        return path;
+    }
+
+    #[test]
+    fn listed_members_are_not_added_again() {
+        let manifest = "[workspace]\nmembers = [\"app\"]\n";
+        let path = workspace("listed", manifest);
+
+        assert!(!add_workspace_member(&path, "app").unwrap());
+        assert_eq!(
+            fs::read_to_string(path.join(MANIFEST_NAME)).unwrap(),
+            manifest
+        );
+
+        assert!(add_workspace_member(&path, "lib").unwrap());
+        assert_eq!(
+            fs::read_to_string(path.join(MANIFEST_NAME)).unwrap(),
+            "[workspace]\nmembers = [\"app\", \"lib\"]\n"
+        );
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn broken_workspaces_stop_init_before_writing() {
+        let path = workspace("broken", "[workspace]\nmembers = \"app\"\n");
+
+        assert!(init_package(&path.join("app"), ProjectInit::Binary).is_err());
+        assert!(!path.join("app").join(MANIFEST_NAME).exists());
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn escapes_package_names() {
+        let text = manifest_text("quote\"and\\slash", ProjectInit::Staticlib);
+
+        let document: toml_edit::DocumentMut = text.parse().unwrap();
+
+        assert_eq!(
+            document["package"]["name"].as_str(),
+            Some("quote\"and\\slash")
+        );
+        assert_eq!(
+            manifest_text("app", ProjectInit::Binary),
+            "[package]\nname = \"app\"\noutput = \"executable\"\n"
+        );
+    }
+}
@@ -1,13 +1,26 @@
-use std::{fmt::format, fs, ops::Deref, path::PathBuf, process::exit};
+#![allow(clippy::needless_return)]
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, exit},
+};
 
 use clap::Parser;
 use doggo_core::{
-    compiler_backend::{ClangCompilerBackend, ExtraCompileOptions, OptLevel},
-    file_up_to_date, get_default_target,
+    build::{BuildConfig, build, execute},
+    compiler_backend::{CompilerBackend, CompilerKind},
+    features::FeatureSelection,
     manifest::PackageKind,
-    project::{Package, Workspace},
+    plan::{ActionKind, BuildPlan},
+    profile::Profile,
+    project::Workspace,
+    scheduler::default_job_count,
+    target::Target,
 };
 
+mod init;
+
 #[derive(clap_derive::Parser)]
 #[command(name = "Doggo")]
 #[command(about = "Bulding C/C++, without the fluff!", long_about = None)]
@@ -20,16 +33,23 @@ struct Cli {
 enum Commands {
     /// Builds the project.
     Build {
+        #[command(flatten)]
+        build_args: BuildArgs,
+        /// Package to build, can be given more than once.
         #[arg(short, long)]
-        release: bool,
-        #[arg(short, long)]
-        project: Option<String>,
+        project: Vec<String>,
+        /// Builds every member of the workspace.
+        #[arg(long, conflicts_with = "project")]
+        workspace: bool,
+        /// Prints the commands that would run, without running them.
+        #[arg(long)]
+        dry_run: bool,
     },
 
     /// Runs the project.
     Run {
-        #[arg(short, long)]
-        release: bool,
+        #[command(flatten)]
+        build_args: BuildArgs,
         #[arg(short, long)]
         project: Option<String>,
         #[arg(last = true)]
@@ -38,8 +58,12 @@ enum Commands {
 
     /// Generates a compile_commands.json file.
     #[command(name = "idegen")]
-    GenCompileCommands,
+    IdeGen {
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
 
+    /// Creates a new package in an existing directory.
     Init {
         #[arg(long, short, global = true)]
         path: Option<PathBuf>,
@@ -47,6 +71,98 @@ enum Commands {
         #[command(subcommand)]
         subcommand: Option<ProjectInit>,
     },
+
+    /// Creates a new package in a new directory.
+    New {
+        path: PathBuf,
+
+        #[command(subcommand)]
+        subcommand: Option<ProjectInit>,
+    },
+}
+
+#[derive(clap_derive::Args)]
+struct ConfigArgs {
+    /// Shorthand for `--profile release`.
+    #[arg(short, long, conflicts_with = "profile")]
+    release: bool,
+    /// Profile to build with, defaults to dev.
+    #[arg(long)]
+    profile: Option<String>,
+    /// Target triple to build for, defaults to `build.target` or the host.
+    #[arg(long)]
+    target: Option<Target>,
+    /// Features to enable, either `<feature>` or `<package>/<feature>`.
+    #[arg(short = 'F', long, value_delimiter = ',')]
+    features: Vec<String>,
+    /// Enables every feature of the selected packages.
+    #[arg(long)]
+    all_features: bool,
+    /// Doesn't enable the `default` feature of the selected packages.
+    #[arg(long)]
+    no_default_features: bool,
+    /// Compiler to build with, `clang` or `gcc`. Defaults to `build.compiler` or Clang.
+    #[arg(long)]
+    compiler: Option<CompilerKind>,
+}
+
+impl ConfigArgs {
+    fn profile_name(&self) -> &str {
+        if self.release {
+            return Profile::RELEASE;
+        }
+
+        return self.profile.as_deref().unwrap_or(Profile::DEV);
+    }
+
+    /// Loads the workspace for the selected target.
+    fn load_workspace(&self) -> Result<Workspace, Box<dyn std::error::Error>> {
+        return Ok(Workspace::load("./".into(), self.target.as_ref())?.ok_or(CliError::NoManifest)?);
+    }
+
+    /// Also works out the features of everything `roots` needs.
+    fn resolve(
+        &self,
+        workspace: &mut Workspace,
+        roots: &[usize],
+    ) -> Result<BuildConfig, Box<dyn std::error::Error>> {
+        let target = workspace.target.clone();
+
+        workspace.resolve_features(
+            roots,
+            &FeatureSelection {
+                features: self.features.clone(),
+                all_features: self.all_features,
+                no_default_features: self.no_default_features,
+            },
+        )?;
+
+        return Ok(BuildConfig::new(
+            workspace,
+            workspace.profile(self.profile_name())?,
+            target,
+        ));
+    }
+
+    fn backend(
+        &self,
+        workspace: &Workspace,
+        config: &BuildConfig,
+    ) -> std::io::Result<Box<dyn CompilerBackend>> {
+        return self
+            .compiler
+            .unwrap_or(workspace.compiler)
+            .backend(&config.target);
+    }
+}
+
+#[derive(clap_derive::Args)]
+struct BuildArgs {
+    #[command(flatten)]
+    config_args: ConfigArgs,
+    /// Number of parallel jobs, defaults to the number of CPUs.
+    #[arg(short, long)]
+    jobs: Option<usize>,
 }
 
 #[derive(clap_derive::Subcommand, Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -57,178 +173,189 @@ enum ProjectInit {
     Binary,
 }
 
+#[derive(Debug, thiserror::Error)]
+enum CliError {
+    #[error("No Doggo.toml found in the current directory or any of its parents.")]
+    NoManifest,
+    #[error("Can only run one package, but {} were selected. Pick one with --project.", .0.len())]
+    TooManyPackages(Vec<String>),
+    #[error("Package {0} is a {1:?}, only executables can be run.")]
+    NotExecutable(String, PackageKind),
+    #[error("A manifest already exists at {0}.")]
+    ManifestExists(PathBuf),
+    #[error("Destination {0} already exists, use `doggo init` instead.")]
+    PathExists(PathBuf),
+    #[error("Cannot derive a package name from {0}.")]
+    InvalidPackageName(PathBuf),
+    #[error("Workspace manifest {0} is malformed.")]
+    MalformedWorkspace(PathBuf),
+}
+
 fn unwrap_fancy<T>(res: Result<T, Box<dyn std::error::Error>>) -> T {
     return match res {
         Err(e) => {
             eprintln!("{}", e);
-            exit(0);
+            exit(1);
         }
         Ok(t) => t,
     };
 }
 
-fn compiled_path(package_name: &str) -> PathBuf {
-    return PathBuf::new().join(".doggo").join(package_name);
-}
-
-fn modify_filename(
-    package: &Package,
-    compiler: &ClangCompilerBackend,
-    extra_options: &ExtraCompileOptions,
-    base_name: &str,
-) -> String {
-    return match package.output {
-        PackageKind::Executable => format!(
-            "{}.{}",
-            base_name,
-            compiler.get_executable_suffix(extra_options)
-        ),
-        PackageKind::DynamicLibrary => format!(
-            "{}{}.{}",
-            compiler.get_library_prefix(extra_options),
-            base_name,
-            compiler.get_dynamic_suffix(extra_options)
-        ),
-        PackageKind::StaticLibrary => format!(
-            "{}{}.{}",
-            compiler.get_library_prefix(extra_options),
-            base_name,
-            compiler.get_static_suffix(extra_options)
-        ),
-    };
+#[derive(serde::Serialize)]
+struct CompileCommand {
+    directory: String,
+    file: String,
+    arguments: Vec<String>,
+    output: String,
 }
 
-fn build_package(
+fn gen_compile_commands(
     workspace: &Workspace,
-    package: &Package,
-    compiler: &ClangCompilerBackend,
-    extra_options: &ExtraCompileOptions,
+    plan: &BuildPlan,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let compiled = compiled_path(&package.name.get());
+    let directory = workspace.path.to_str().unwrap().to_string();
 
-    fs::create_dir_all(&compiled)?;
+    let mut commands = vec![];
 
-    let mut objects = vec![];
+    for action in &plan.actions {
+        let ActionKind::Compile { source, .. } = &action.kind else {
+            continue;
+        };
 
-    package.visit(
-        |path| {
-            let base_path = compiled
-                .join(path)
-                .with_extension(compiler.get_object_suffix(extra_options));
-            let output = base_path.to_str().unwrap();
-
-            objects.push(output.to_string());
-
-            let def_file = base_path.with_extension("d");
-            let dep_file = def_file.to_str().unwrap();
-
-            if file_up_to_date(dep_file, output)? {
-                return Ok(());
-            }
+        commands.push(CompileCommand {
+            directory: directory.clone(),
+            file: source.clone(),
+            arguments: action.argv.clone(),
+            output: action.output().to_string(),
+        });
+    }
 
-            compiler.compile_object(
-                &package.resolve_source(path),
-                output,
-                &[],
-                &[],
-                extra_options,
-                false,
-            )?;
+    let json = serde_json::to_string_pretty(&commands)?;
 
-            return Ok(());
-        },
-        &["c", "cpp", "cxx", "c++", "cc", "s", "asm"],
-    )?;
-
-    let compiled = compiled.with_file_name(&modify_filename(
-        package,
-        compiler,
-        extra_options,
-        compiled.file_name().unwrap().to_str().unwrap(),
-    ));
-
-    let output = compiled.to_str().unwrap();
-
-    if let PackageKind::StaticLibrary = package.output {
-        compiler.archive_objects(&objects, output, extra_options)?;
-    } else {
-        compiler.link_objects(
-            &objects,
-            output,
-            &[],
-            &[],
-            &[],
-            package.output == PackageKind::DynamicLibrary,
-            extra_options,
-        )?;
-    }
+    fs::write(workspace.path.join("compile_commands.json"), json)?;
 
     return Ok(());
 }
 
-fn build(
-    workspace: &Workspace,
-    compiler: &ClangCompilerBackend,
-    release: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let Some(current_member) = workspace.current_member else {
-        return Ok(());
-    };
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match cli.command {
+        Commands::Build {
+            build_args,
+            project,
+            workspace: all,
+            dry_run,
+        } => {
+            let mut workspace = build_args.config_args.load_workspace()?;
 
-    let current_member = &workspace.members[current_member];
-
-    let extra_options = ExtraCompileOptions {
-        opt_level: if release {
-            OptLevel::Three
-        } else {
-            OptLevel::Zero
-        },
-        generate_debug: true,
-        lto: current_member.lto,
-        target: get_default_target().to_string(),
-    };
+            let roots = workspace.select_members(&project, all)?;
 
-    build_package(workspace, &current_member, compiler, &extra_options)?;
+            let config = build_args.config_args.resolve(&mut workspace, &roots)?;
 
-    return Ok(());
-}
+            let compiler = build_args.config_args.backend(&workspace, &config)?;
 
-fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
-    match cli.command {
-        Commands::Build { release, project } => {
-            let workspace = Workspace::load("./".into(), project)?.unwrap();
+            let names = roots
+                .iter()
+                .map(|it| workspace.members[*it].name.get().to_string())
+                .collect::<Vec<_>>();
+
+            println!(
+                "Building {} ({}, {})",
+                names.join(", "),
+                config.profile.name,
+                config.target
+            );
 
-            let compiler = ClangCompilerBackend::new()?;
+            let plan = BuildPlan::new(&workspace, compiler.as_ref(), &config, &roots)?;
 
-            println!("Build {:?}", release);
+            if dry_run {
+                for id in plan.pending()? {
+                    println!("{}", plan.actions[id].command_line());
+                }
 
-            build(&workspace, &compiler, release)?;
+                return Ok(());
+            }
+
+            execute(
+                &plan,
+                compiler.as_ref(),
+                build_args.jobs.unwrap_or_else(default_job_count),
+            )?;
         }
 
         Commands::Run {
-            args,
-            release,
+            build_args,
             project,
+            args,
         } => {
-            let workspace = Workspace::load("./".into(), project)?.unwrap();
+            let mut workspace = build_args.config_args.load_workspace()?;
+
+            let roots = workspace.select_members(project.as_slice(), false)?;
+
+            if roots.len() != 1 {
+                return Err(CliError::TooManyPackages(
+                    roots
+                        .iter()
+                        .map(|it| workspace.members[*it].name.get().to_string())
+                        .collect(),
+                )
+                .into());
+            }
 
-            let compiler = ClangCompilerBackend::new()?;
+            let package = &workspace.members[roots[0]];
 
-            println!("Run {:?} {:?}", args, release);
+            if package.output != PackageKind::Executable {
+                return Err(CliError::NotExecutable(
+                    package.name.get().to_string(),
+                    package.output.clone(),
+                )
+                .into());
+            }
+
+            let config = build_args.config_args.resolve(&mut workspace, &roots)?;
+
+            let compiler = build_args.config_args.backend(&workspace, &config)?;
+
+            let output = build(
+                &workspace,
+                compiler.as_ref(),
+                &config,
+                &roots,
+                build_args.jobs.unwrap_or_else(default_job_count),
+            )?;
+
+            let status = Command::new(&output.outputs[&roots[0]])
+                .args(&args)
+                .status()?;
+
+            exit(status.code().unwrap_or(1));
         }
 
-        Commands::GenCompileCommands => {
-            let workspace = Workspace::load("./".into(), None)?.unwrap();
+        Commands::IdeGen { config_args } => {
+            let mut workspace = config_args.load_workspace()?;
+
+            let members = (0..workspace.members.len()).collect::<Vec<_>>();
+
+            let config = config_args.resolve(&mut workspace, &members)?;
+
+            let compiler = config_args.backend(&workspace, &config)?;
 
-            let compiler = ClangCompilerBackend::new()?;
+            let plan = BuildPlan::new(&workspace, compiler.as_ref(), &config, &members)?;
 
-            println!("Gen");
+            gen_compile_commands(&workspace, &plan)?;
         }
 
         Commands::Init { subcommand, path } => {
-            let subcommand = subcommand.unwrap_or_default();
+            let path = path.unwrap_or_else(|| "./".into());
+
+            init::init_package(&path, subcommand.unwrap_or_default())?;
+        }
+
+        Commands::New { path, subcommand } => {
+            if path.exists() {
+                return Err(CliError::PathExists(path).into());
+            }
 
-            println!("Init {:?} {:?}", subcommand, path);
+            init::init_package(&path, subcommand.unwrap_or_default())?;
         }
     }
 
@@ -0,0 +1,1109 @@
+use std::{collections::HashSet, fmt::Write, io, path::Path};
+
+use crate::walk_dir;
+
+/// Enough of a Rust tokenizer to find `#[no_mangle] extern "C"` items. Comments are
+/// dropped, and lifetimes come out as a `'` followed by the name.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(String),
+    Punct(String),
+}
+
+impl Token {
+    fn is(&self, text: &str) -> bool {
+        return match self {
+            Token::Ident(it) | Token::Punct(it) => it == text,
+            Token::Literal(_) => false,
+        };
+    }
+
+    fn text(&self) -> &str {
+        return match self {
+            Token::Ident(it) | Token::Literal(it) | Token::Punct(it) => it,
+        };
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    let is_ident = |it: char| it.is_alphanumeric() || it == '_';
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            let mut depth = 0;
+
+            while i < chars.len() {
+                if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                    depth += 1;
+                    i += 2;
+                } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    depth -= 1;
+                    i += 2;
+
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        } else if c == 'r' && (next == Some('"') || next == Some('#')) && {
+            let hashes = chars[i + 1..].iter().take_while(|it| **it == '#').count();
+
+            chars.get(i + 1 + hashes) == Some(&'"')
+        } {
+            let hashes = chars[i + 1..].iter().take_while(|it| **it == '#').count();
+            let start = i + 2 + hashes;
+            let closing: String = std::iter::once('"')
+                .chain(std::iter::repeat_n('#', hashes))
+                .collect();
+
+            let rest: String = chars[start..].iter().collect();
+            let end = rest.find(&closing).unwrap_or(rest.len());
+
+            tokens.push(Token::Literal(rest[..end].to_string()));
+
+            i = start + rest[..end].chars().count() + closing.len();
+        } else if c == '"' {
+            let mut value = String::new();
+
+            i += 1;
+
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+
+                if let Some(it) = chars.get(i) {
+                    value.push(*it);
+                }
+
+                i += 1;
+            }
+
+            tokens.push(Token::Literal(value));
+
+            i += 1;
+        } else if c == '\'' && (next == Some('\\') || chars.get(i + 2) == Some(&'\'')) {
+            // A char literal, lifetimes never have a closing quote.
+            i += 1;
+
+            while i < chars.len() && chars[i] != '\'' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+
+                i += 1;
+            }
+
+            tokens.push(Token::Literal(String::new()));
+
+            i += 1;
+        } else if is_ident(c) {
+            // Raw identifiers, like `r#type`.
+            if c == 'r' && next == Some('#') {
+                i += 2;
+            }
+
+            let start = i;
+
+            while i < chars.len() && is_ident(chars[i]) {
+                i += 1;
+            }
+
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if (c == '-' && next == Some('>')) || (c == ':' && next == Some(':')) {
+            tokens.push(Token::Punct(chars[i..i + 2].iter().collect()));
+
+            i += 2;
+        } else {
+            tokens.push(Token::Punct(c.to_string()));
+
+            i += 1;
+        }
+    }
+
+    return tokens;
+}
+
+/// Whether the token opens (1) or closes (-1) a bracket. `<` and `>` only count when
+/// `angles` is set, since outside of types they're comparisons and shifts.
+fn bracket_depth(token: &Token, angles: bool) -> i32 {
+    if matches!(token, Token::Literal(_)) {
+        return 0;
+    }
+
+    return match token.text() {
+        "(" | "[" | "{" => 1,
+        ")" | "]" | "}" => -1,
+        "<" if angles => 1,
+        ">" if angles => -1,
+        _ => 0,
+    };
+}
+
+/// Index just past the group opened at `start`, which has to be one of `(`, `[`, `{` or `<`.
+/// Other brackets always pair up, so angle brackets only count in a `<` group.
+fn skip_group(tokens: &[Token], start: usize) -> usize {
+    let angles = tokens.get(start).is_some_and(|it| it.is("<"));
+
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < tokens.len() {
+        depth += bracket_depth(&tokens[i], angles);
+
+        i += 1;
+
+        if depth == 0 {
+            break;
+        }
+    }
+
+    return i;
+}
+
+/// Splits on commas that aren't nested in brackets. Empty parts are dropped. `types`
+/// is for lists of types, where commas can be nested in generics.
+fn split_commas(tokens: &[Token], types: bool) -> Vec<&[Token]> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        depth += bracket_depth(token, types);
+
+        if depth == 0 && token.is(",") {
+            parts.push(&tokens[start..i]);
+            start = i + 1;
+        }
+    }
+
+    parts.push(&tokens[start..]);
+
+    return parts.into_iter().filter(|it| !it.is_empty()).collect();
+}
+
+#[derive(Default)]
+struct Attributes {
+    export_name: Option<String>,
+    no_mangle: bool,
+    repr: Option<String>,
+}
+
+impl Attributes {
+    fn read(&mut self, tokens: &[Token]) {
+        // `#[unsafe(no_mangle)]` is the 2024 edition spelling.
+        let tokens = match tokens {
+            [unsafe_, open, inner @ .., close]
+                if unsafe_.is("unsafe") && open.is("(") && close.is(")") =>
+            {
+                inner
+            }
+            _ => tokens,
+        };
+
+        match tokens {
+            [name] if name.is("no_mangle") => self.no_mangle = true,
+            [name, equals, Token::Literal(value)] if name.is("export_name") && equals.is("=") => {
+                self.export_name = Some(value.clone());
+            }
+            [name, open, inner @ .., close] if name.is("repr") && open.is("(") && close.is(")") => {
+                self.repr = split_commas(inner, false)
+                    .into_iter()
+                    .filter_map(|it| it.first())
+                    .map(|it| it.text().to_string())
+                    .find(|it| it != "align" && it != "packed");
+            }
+            _ => {}
+        }
+    }
+
+    fn exported_name(&self, name: &str) -> Option<String> {
+        if let Some(name) = &self.export_name {
+            return Some(name.clone());
+        }
+
+        return self.no_mangle.then(|| name.to_string());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CType {
+    Void,
+    Named(String),
+    Pointer { pointee: Box<CType>, constant: bool },
+}
+
+impl CType {
+    /// Writes a declaration of `name` with this type, which is just the type if `name` is empty.
+    fn declare(&self, name: &str) -> String {
+        let separated = if name.is_empty() {
+            String::new()
+        } else {
+            format!(" {name}")
+        };
+
+        return match self {
+            CType::Void => format!("void{separated}"),
+            CType::Named(it) => format!("{it}{separated}"),
+            CType::Pointer { pointee, constant } => {
+                let inner = match (&**pointee, constant) {
+                    (CType::Pointer { .. }, true) => format!("{} const", pointee.declare("")),
+                    (_, true) => format!("const {}", pointee.declare("")),
+                    (_, false) => pointee.declare(""),
+                };
+
+                if inner.ends_with('*') {
+                    format!("{inner}*{name}")
+                } else {
+                    format!("{inner} *{name}")
+                }
+            }
+        };
+    }
+
+    /// Struct names this needs to be complete, so not the ones behind pointers.
+    fn by_value(&self) -> Option<&str> {
+        return match self {
+            CType::Named(it) => Some(it),
+            _ => None,
+        };
+    }
+}
+
+fn primitive(name: &str) -> Option<&'static str> {
+    return Some(match name {
+        "i8" => "int8_t",
+        "i16" => "int16_t",
+        "i32" => "int32_t",
+        "i64" => "int64_t",
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "u64" => "uint64_t",
+        "isize" => "intptr_t",
+        "usize" => "uintptr_t",
+        "f32" => "float",
+        "f64" => "double",
+        "bool" => "bool",
+        "char" => "uint32_t",
+        "c_char" => "char",
+        "c_schar" => "signed char",
+        "c_uchar" => "unsigned char",
+        "c_short" => "short",
+        "c_ushort" => "unsigned short",
+        "c_int" => "int",
+        "c_uint" => "unsigned int",
+        "c_long" => "long",
+        "c_ulong" => "unsigned long",
+        "c_longlong" => "long long",
+        "c_ulonglong" => "unsigned long long",
+        "c_float" => "float",
+        "c_double" => "double",
+        "size_t" => "size_t",
+        "ssize_t" => "ptrdiff_t",
+        _ => return None,
+    });
+}
+
+/// Converts a Rust type to C. Types that aren't primitives are assumed to be structs,
+/// declared in the crate or opaque.
+fn convert_type(tokens: &[Token]) -> Option<CType> {
+    let mut tokens = tokens;
+
+    // Paths like `core::ffi::c_int` just use the last segment.
+    while let [_, separator, rest @ ..] = tokens
+        && separator.is("::")
+    {
+        tokens = rest;
+    }
+
+    if let [leading, rest @ ..] = tokens
+        && leading.is("::")
+    {
+        return convert_type(rest);
+    }
+
+    return match tokens {
+        [] => Some(CType::Void),
+        [open, close] if open.is("(") && close.is(")") => Some(CType::Void),
+        [never] if never.is("!") => Some(CType::Void),
+        [star, kind, rest @ ..] if star.is("*") && (kind.is("const") || kind.is("mut")) => {
+            Some(CType::Pointer {
+                pointee: Box::new(convert_type(rest)?),
+                constant: kind.is("const"),
+            })
+        }
+        [ampersand, rest @ ..] if ampersand.is("&") => {
+            let mut rest = rest;
+
+            if let [quote, _, after @ ..] = rest
+                && quote.is("'")
+            {
+                rest = after;
+            }
+
+            let (constant, rest) = match rest {
+                [mutable, after @ ..] if mutable.is("mut") => (false, after),
+                _ => (true, rest),
+            };
+
+            // Slices and trait objects are fat pointers.
+            if rest.first().is_some_and(|it| it.is("[") || it.is("dyn")) {
+                return None;
+            }
+
+            Some(CType::Pointer {
+                pointee: Box::new(convert_type(rest)?),
+                constant,
+            })
+        }
+        [Token::Ident(name), open, inner @ .., close]
+            if open.is("<")
+                && close.is(">")
+                && ["Option", "NonNull", "Box"].contains(&name.as_str()) =>
+        {
+            let inner = convert_type(inner)?;
+
+            match (name.as_str(), inner) {
+                ("NonNull" | "Box", inner) => Some(CType::Pointer {
+                    pointee: Box::new(inner),
+                    constant: false,
+                }),
+                (_, pointer @ CType::Pointer { .. }) => Some(pointer),
+                _ => None,
+            }
+        }
+        [Token::Ident(name)] if name == "c_void" => Some(CType::Void),
+        [Token::Ident(name)] => Some(CType::Named(
+            primitive(name)
+                .map(str::to_string)
+                .unwrap_or_else(|| name.clone()),
+        )),
+        _ => None,
+    };
+}
+
+/// Writes a discriminant as C, which only works for plain arithmetic on literals.
+fn convert_expression(tokens: &[Token]) -> Option<String> {
+    let mut out = String::new();
+    let mut word = false;
+
+    for token in tokens {
+        match token {
+            // Casts and paths to other constants don't exist in C.
+            Token::Ident(it) if it == "as" => return None,
+            Token::Punct(it) if it == "::" => return None,
+            Token::Literal(_) => return None,
+            Token::Ident(it) => {
+                if word {
+                    out.push(' ');
+                }
+
+                out.push_str(it);
+                word = true;
+            }
+            Token::Punct(it) => {
+                out.push_str(it);
+                word = false;
+            }
+        }
+    }
+
+    return Some(out);
+}
+
+struct Function {
+    name: String,
+    returns: CType,
+    parameters: Vec<(String, CType)>,
+}
+
+struct Struct {
+    name: String,
+    fields: Vec<(String, CType)>,
+}
+
+struct Enum {
+    name: String,
+    repr: String,
+    variants: Vec<(String, Option<String>)>,
+}
+
+struct Static {
+    name: String,
+    ty: CType,
+    mutable: bool,
+}
+
+/// Everything a crate exports to C, found by [`scan_crate`].
+#[derive(Default)]
+pub struct Exports {
+    functions: Vec<Function>,
+    structs: Vec<Struct>,
+    enums: Vec<Enum>,
+    aliases: Vec<(String, CType)>,
+    statics: Vec<Static>,
+    /// Exports with types that can't be written in C, kept for a comment in the header.
+    skipped: Vec<String>,
+}
+
+impl Exports {
+    fn scan(&mut self, tokens: &[Token]) {
+        let mut attributes = Attributes::default();
+        let mut i = 0;
+
+        while i < tokens.len() {
+            if tokens[i].is("#") {
+                let open = if tokens.get(i + 1).is_some_and(|it| it.is("!")) {
+                    i + 2
+                } else {
+                    i + 1
+                };
+
+                let end = skip_group(tokens, open);
+
+                if end > open + 1 {
+                    attributes.read(&tokens[open + 1..end - 1]);
+                }
+
+                i = end;
+
+                continue;
+            }
+
+            let start = i;
+
+            if tokens[i].is("pub") {
+                i += 1;
+
+                if tokens.get(i).is_some_and(|it| it.is("(")) {
+                    i = skip_group(tokens, i);
+                }
+            }
+
+            while tokens
+                .get(i)
+                .is_some_and(|it| it.is("unsafe") || it.is("const") || it.is("extern"))
+            {
+                if tokens[i].is("extern") && matches!(tokens.get(i + 1), Some(Token::Literal(_))) {
+                    i += 1;
+                }
+
+                i += 1;
+            }
+
+            let abi = tokens[start..i]
+                .iter()
+                .position(|it| it.is("extern"))
+                .and_then(|it| tokens.get(start + it + 1));
+
+            let extern_c =
+                abi.is_some_and(|it| it.text() == "C" || it.text() == "C-unwind" || it.is("fn"));
+
+            let next = match tokens.get(i) {
+                Some(Token::Ident(it)) => it.as_str(),
+                _ => {
+                    attributes = Attributes::default();
+                    i += 1;
+
+                    continue;
+                }
+            };
+
+            i = match next {
+                "fn" if extern_c => self.scan_function(tokens, i, &attributes),
+                "static" => self.scan_static(tokens, i, &attributes),
+                "struct" if attributes.repr.is_some() => self.scan_struct(tokens, i),
+                "enum" if attributes.repr.is_some() => {
+                    self.scan_enum(tokens, i, attributes.repr.clone().unwrap())
+                }
+                "type" if tokens[start].is("pub") => self.scan_alias(tokens, i),
+                // Items in modules and `extern` blocks can export things too.
+                "mod" => i + 2,
+                _ => i + 1,
+            };
+
+            attributes = Attributes::default();
+        }
+    }
+
+    fn scan_function(&mut self, tokens: &[Token], start: usize, attributes: &Attributes) -> usize {
+        let Some(name) = tokens.get(start + 1).map(|it| it.text().to_string()) else {
+            return tokens.len();
+        };
+
+        let open = start + 2;
+
+        if !tokens.get(open).is_some_and(|it| it.is("(")) {
+            // Generic functions can't be exported.
+            return open;
+        }
+
+        let close = skip_group(tokens, open);
+
+        let body = tokens[close..]
+            .iter()
+            .position(|it| it.is("{") || it.is(";") || it.is("where"))
+            .map(|it| close + it)
+            .unwrap_or(tokens.len());
+
+        let Some(exported) = attributes.exported_name(&name) else {
+            return body;
+        };
+
+        let returns = match tokens.get(close) {
+            Some(arrow) if arrow.is("->") => convert_type(&tokens[close + 1..body]),
+            _ => Some(CType::Void),
+        };
+
+        let parameters = split_commas(&tokens[open + 1..close - 1], true)
+            .into_iter()
+            .map(|it| {
+                let colon = it.iter().position(|it| it.is(":"))?;
+
+                let name = match &it[..colon] {
+                    [.., Token::Ident(name)] if name != "_" => name.clone(),
+                    _ => String::new(),
+                };
+
+                return Some((name, convert_type(&it[colon + 1..])?));
+            })
+            .collect::<Option<Vec<_>>>();
+
+        match (returns, parameters) {
+            (Some(returns), Some(parameters)) => self.functions.push(Function {
+                name: exported,
+                returns,
+                parameters,
+            }),
+            _ => self.skipped.push(exported),
+        }
+
+        return body;
+    }
+
+    fn scan_static(&mut self, tokens: &[Token], start: usize, attributes: &Attributes) -> usize {
+        let mutable = tokens.get(start + 1).is_some_and(|it| it.is("mut"));
+        let name_index = if mutable { start + 2 } else { start + 1 };
+
+        let end = tokens[start..]
+            .iter()
+            .position(|it| it.is("=") || it.is(";"))
+            .map(|it| start + it)
+            .unwrap_or(tokens.len());
+
+        let Some(name) = tokens.get(name_index).map(|it| it.text().to_string()) else {
+            return end;
+        };
+
+        let Some(exported) = attributes.exported_name(&name) else {
+            return end;
+        };
+
+        match convert_type(&tokens[(name_index + 2).min(end)..end]) {
+            Some(ty) => self.statics.push(Static {
+                name: exported,
+                ty,
+                mutable,
+            }),
+            None => self.skipped.push(exported),
+        }
+
+        return end;
+    }
+
+    fn scan_struct(&mut self, tokens: &[Token], start: usize) -> usize {
+        let Some(name) = tokens.get(start + 1).map(|it| it.text().to_string()) else {
+            return tokens.len();
+        };
+
+        let open = start + 2;
+
+        // Tuple structs, unit structs and generic ones end up opaque.
+        if !tokens.get(open).is_some_and(|it| it.is("{")) {
+            return open;
+        }
+
+        let close = skip_group(tokens, open);
+
+        let fields = split_commas(&tokens[open + 1..close - 1], true)
+            .into_iter()
+            .map(|it| {
+                let mut it = it;
+
+                while let [hash, rest @ ..] = it
+                    && hash.is("#")
+                {
+                    it = &rest[skip_group(rest, 0)..];
+                }
+
+                let colon = it.iter().position(|it| it.is(":"))?;
+
+                return Some((
+                    it[colon - 1].text().to_string(),
+                    convert_type(&it[colon + 1..])?,
+                ));
+            })
+            .collect::<Option<Vec<_>>>();
+
+        match fields {
+            Some(fields) => self.structs.push(Struct { name, fields }),
+            None => self.skipped.push(name),
+        }
+
+        return close;
+    }
+
+    fn scan_enum(&mut self, tokens: &[Token], start: usize, repr: String) -> usize {
+        let Some(name) = tokens.get(start + 1).map(|it| it.text().to_string()) else {
+            return tokens.len();
+        };
+
+        let open = start + 2;
+
+        if !tokens.get(open).is_some_and(|it| it.is("{")) {
+            return open;
+        }
+
+        let close = skip_group(tokens, open);
+
+        let variants = split_commas(&tokens[open + 1..close - 1], false)
+            .into_iter()
+            .map(|it| {
+                return match it {
+                    [Token::Ident(name)] => Some((name.clone(), None)),
+                    [Token::Ident(name), equals, value @ ..] if equals.is("=") => {
+                        Some((name.clone(), Some(convert_expression(value)?)))
+                    }
+                    _ => None,
+                };
+            })
+            .collect::<Option<Vec<_>>>();
+
+        match variants {
+            Some(variants) => self.enums.push(Enum {
+                name,
+                repr,
+                variants,
+            }),
+            // Enums with fields are tagged unions, which this doesn't do.
+            None => self.skipped.push(name),
+        }
+
+        return close;
+    }
+
+    fn scan_alias(&mut self, tokens: &[Token], start: usize) -> usize {
+        let end = tokens[start..]
+            .iter()
+            .position(|it| it.is(";"))
+            .map(|it| start + it)
+            .unwrap_or(tokens.len());
+
+        if let [_, Token::Ident(name), equals, ty @ ..] = &tokens[start..end]
+            && equals.is("=")
+            && let Some(ty) = convert_type(ty)
+        {
+            self.aliases.push((name.clone(), ty));
+        }
+
+        return end;
+    }
+
+    /// Structs in an order where fields by value are always defined first.
+    fn sorted_structs(&self) -> Vec<&Struct> {
+        fn visit<'a>(
+            item: &'a Struct,
+            structs: &'a [Struct],
+            visited: &mut HashSet<&'a str>,
+            order: &mut Vec<&'a Struct>,
+        ) {
+            if !visited.insert(&item.name) {
+                return;
+            }
+
+            for (_, ty) in &item.fields {
+                if let Some(dependency) = ty
+                    .by_value()
+                    .and_then(|name| structs.iter().find(|it| it.name == name))
+                {
+                    visit(dependency, structs, visited, order);
+                }
+            }
+
+            order.push(item);
+        }
+
+        let mut visited = HashSet::new();
+        let mut order = vec![];
+
+        for item in &self.structs {
+            visit(item, &self.structs, &mut visited, &mut order);
+        }
+
+        return order;
+    }
+
+    /// Names used in the exports that aren't declared by them, which we can only declare opaque.
+    fn opaque_types(&self) -> Vec<String> {
+        let mut types: Vec<&CType> = self
+            .functions
+            .iter()
+            .flat_map(|it| std::iter::once(&it.returns).chain(it.parameters.iter().map(|it| &it.1)))
+            .chain(self.statics.iter().map(|it| &it.ty))
+            .chain(
+                self.structs
+                    .iter()
+                    .flat_map(|it| it.fields.iter().map(|it| &it.1)),
+            )
+            .chain(self.aliases.iter().map(|it| &it.1))
+            .collect();
+
+        let mut names = vec![];
+
+        while let Some(ty) = types.pop() {
+            match ty {
+                CType::Pointer { pointee, .. } => types.push(pointee),
+                CType::Named(name) => {
+                    let declared = self.structs.iter().any(|it| &it.name == name)
+                        || self.enums.iter().any(|it| &it.name == name)
+                        || self.aliases.iter().any(|it| &it.0 == name)
+                        || primitive_c_name(name);
+
+                    if !declared && !names.contains(name) {
+                        names.push(name.clone());
+                    }
+                }
+                CType::Void => {}
+            }
+        }
+
+        names.sort();
+
+        return names;
+    }
+
+    /// Writes the header. `guard` is used for the include guard.
+    pub fn header(&self, guard: &str) -> String {
+        let mut out = String::new();
+
+        let guard = guard
+            .chars()
+            .map(|it| {
+                if it.is_ascii_alphanumeric() {
+                    it.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+
+        let _ = writeln!(out, "/* Generated by doggo, don't edit. */");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "#ifndef {guard}_H");
+        let _ = writeln!(out, "#define {guard}_H");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "#include <stdbool.h>");
+        let _ = writeln!(out, "#include <stddef.h>");
+        let _ = writeln!(out, "#include <stdint.h>");
+        let _ = writeln!(out);
+
+        for name in self
+            .opaque_types()
+            .iter()
+            .chain(self.structs.iter().map(|it| &it.name))
+        {
+            let _ = writeln!(out, "typedef struct {name} {name};");
+        }
+
+        for item in &self.enums {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "enum {} {{", item.name);
+
+            for (variant, value) in &item.variants {
+                match value {
+                    Some(value) => {
+                        let _ = writeln!(out, "    {variant} = {value},");
+                    }
+                    None => {
+                        let _ = writeln!(out, "    {variant},");
+                    }
+                }
+            }
+
+            let _ = writeln!(out, "}};");
+
+            let ty = match primitive(&item.repr) {
+                Some(ty) => ty.to_string(),
+                None => format!("enum {}", item.name),
+            };
+
+            let _ = writeln!(out, "typedef {ty} {};", item.name);
+        }
+
+        for (name, ty) in &self.aliases {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "typedef {};", ty.declare(name));
+        }
+
+        for item in self.sorted_structs() {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "struct {} {{", item.name);
+
+            for (name, ty) in &item.fields {
+                let _ = writeln!(out, "    {};", ty.declare(name));
+            }
+
+            let _ = writeln!(out, "}};");
+        }
+
+        let _ = writeln!(out);
+        let _ = writeln!(out, "#ifdef __cplusplus");
+        let _ = writeln!(out, "extern \"C\" {{");
+        let _ = writeln!(out, "#endif");
+
+        if !self.statics.is_empty() {
+            let _ = writeln!(out);
+        }
+
+        for item in &self.statics {
+            let ty = match (&item.ty, item.mutable) {
+                (CType::Pointer { .. }, false) => format!("{} const", item.ty.declare("")),
+                (_, false) => format!("const {}", item.ty.declare("")),
+                (_, true) => item.ty.declare(""),
+            };
+
+            let _ = writeln!(out, "extern {ty} {};", item.name);
+        }
+
+        for function in &self.functions {
+            let parameters = if function.parameters.is_empty() {
+                "void".to_string()
+            } else {
+                function
+                    .parameters
+                    .iter()
+                    .map(|(name, ty)| ty.declare(name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+
+            let _ = writeln!(out);
+            let _ = writeln!(
+                out,
+                "{};",
+                function
+                    .returns
+                    .declare(&format!("{}({parameters})", function.name))
+            );
+        }
+
+        for name in &self.skipped {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "/* {name} uses types that can't be written in C. */");
+        }
+
+        let _ = writeln!(out);
+        let _ = writeln!(out, "#ifdef __cplusplus");
+        let _ = writeln!(out, "}}");
+        let _ = writeln!(out, "#endif");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "#endif");
+
+        return out;
+    }
+}
+
+/// Whether a converted type is one of C's own, so it doesn't need declaring.
+fn primitive_c_name(name: &str) -> bool {
+    return name.contains(' ')
+        || [
+            "int8_t",
+            "int16_t",
+            "int32_t",
+            "int64_t",
+            "uint8_t",
+            "uint16_t",
+            "uint32_t",
+            "uint64_t",
+            "intptr_t",
+            "uintptr_t",
+            "float",
+            "double",
+            "bool",
+            "char",
+            "short",
+            "int",
+            "long",
+            "size_t",
+            "ptrdiff_t",
+        ]
+        .contains(&name);
+}
+
+/// Finds the exports in every `.rs` file under `src_dir`. Returns them, and the files
+/// that were read, for the depfile.
+pub fn scan_crate(src_dir: &Path) -> io::Result<(Exports, Vec<String>)> {
+    let mut exports = Exports::default();
+    let mut files = vec![];
+
+    walk_dir(src_dir, &mut |file: &str| {
+        if !file.ends_with(".rs") {
+            return Ok(());
+        }
+
+        let path = src_dir.join(file);
+
+        exports.scan(&tokenize(&std::fs::read_to_string(&path)?));
+
+        files.push(path.to_str().unwrap().to_string());
+
+        return Ok(());
+    })?;
+
+    files.sort();
+
+    return Ok((exports, files));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(source: &str) -> Exports {
+        let mut exports = Exports::default();
+
+        exports.scan(&tokenize(source));
+
+        return exports;
+    }
+
+    fn function_names(exports: &Exports) -> Vec<&str> {
+        return exports
+            .functions
+            .iter()
+            .map(|it| it.name.as_str())
+            .collect();
+    }
+
+    #[test]
+    fn enums_with_shift_discriminants() {
+        let exports = scan(
+            "#[repr(C)] pub enum Flags { A = 1 << 0, B = 1 << 1, C = (1 << 4) >> 2, D }\n\
+             #[no_mangle] pub extern \"C\" fn after_flags() -> Flags { Flags::A }",
+        );
+
+        let [flags] = exports.enums.as_slice() else {
+            panic!("expected one enum");
+        };
+
+        assert_eq!(
+            flags.variants,
+            [
+                ("A".into(), Some("1<<0".into())),
+                ("B".into(), Some("1<<1".into())),
+                ("C".into(), Some("(1<<4)>>2".into())),
+                ("D".into(), None),
+            ]
+        );
+
+        assert_eq!(function_names(&exports), ["after_flags"]);
+
+        let header = exports.header("flags");
+
+        assert!(header.contains("    A = 1<<0,\n"));
+        assert!(header.contains("Flags after_flags(void);"));
+    }
+
+    #[test]
+    fn enums_with_discriminants_c_cant_write_are_skipped() {
+        let exports = scan(
+            "#[repr(u8)] pub enum Casted { A = 1 as u8 }\n\
+             #[repr(C)] pub enum Shape { Circle(f32), Square { side: f32 } }\n\
+             #[no_mangle] pub extern \"C\" fn last() {}",
+        );
+
+        assert!(exports.enums.is_empty());
+        assert_eq!(exports.skipped, ["Casted", "Shape"]);
+        assert_eq!(function_names(&exports), ["last"]);
+    }
+
+    #[test]
+    fn generics_in_signatures() {
+        let exports = scan(
+            "#[no_mangle] pub extern \"C\" fn lookup(map: Option<&mut Map>, key: *const c_char, \
+             out: Option<NonNull<u8>>) -> Option<Box<Entry>> { None }\n\
+             #[no_mangle] pub extern \"C\" fn generic<T>(value: T) {}\n\
+             #[no_mangle] pub extern \"C\" fn pair(value: Pair<u8, u16>) {}",
+        );
+
+        assert_eq!(function_names(&exports), ["lookup"]);
+        assert_eq!(exports.skipped, ["pair"]);
+
+        let [lookup] = exports.functions.as_slice() else {
+            unreachable!();
+        };
+
+        assert_eq!(lookup.parameters.len(), 3);
+
+        assert!(
+            exports
+                .header("lookup")
+                .contains("Entry *lookup(Map *map, const char *key, uint8_t *out);")
+        );
+    }
+
+    #[test]
+    fn nested_groups() {
+        let exports = scan(
+            "#[cfg(any(unix, windows))]\n\
+             #[repr(C)] pub struct Packet { #[cfg(all(a, b))] header: [u8; 4], len: u32, data: *mut u8 }\n\
+             #[repr(C)] pub struct Wrapper { packet: Packet, callback: Option<&Callback> }\n\
+             mod inner { #[no_mangle] pub extern \"C\" fn nested(packet: *const Packet) -> u32 { (1 << 2) as u32 } }",
+        );
+
+        // Arrays can't be struct fields yet, so that one gets skipped.
+        assert_eq!(exports.skipped, ["Packet"]);
+
+        let [wrapper] = exports.structs.as_slice() else {
+            panic!("expected one struct");
+        };
+
+        assert_eq!(wrapper.name, "Wrapper");
+        assert_eq!(wrapper.fields.len(), 2);
+
+        assert_eq!(function_names(&exports), ["nested"]);
+    }
+
+    #[test]
+    fn items_after_a_parse_failure() {
+        let exports = scan(
+            "#[no_mangle] pub extern \"C\" fn first(x: &[u8]) {}\n\
+             #[repr(C)] pub enum Broken { A = 1 < 2, B = 3 > 1 }\n\
+             #[no_mangle] pub extern \"C\" fn after_flags(a: i32) -> i32 { a }\n\
+             #[no_mangle] pub static LAST: u32 = 1 << 3;",
+        );
+
+        assert_eq!(exports.skipped, ["first"]);
+        assert_eq!(function_names(&exports), ["after_flags"]);
+        assert_eq!(exports.enums.len(), 1);
+        assert_eq!(exports.statics.len(), 1);
+        assert_eq!(exports.statics[0].name, "LAST");
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    CARGO_DIR, OBJECT_DIR,
+    cargo::CargoCrate,
+    compiler_backend::{CompilerBackend, ExtraCompileOptions},
+    features::feature_define,
+    plan::{Action, ActionKind, BuildPlan},
+    profile::Profile,
+    project::{Workspace, WorkspaceError},
+    scheduler::Scheduler,
+    target::Target,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BuildError {
+    #[error("{0}")]
+    Workspace(WorkspaceError),
+    #[error("{0}")]
+    Io(io::Error),
+    #[error("Package {0} depends on {1}, which is an executable and can't be linked.")]
+    DependsOnExecutable(String, String),
+}
+
+impl From<WorkspaceError> for BuildError {
+    fn from(value: WorkspaceError) -> Self {
+        return Self::Workspace(value);
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(value: io::Error) -> Self {
+        return Self::Io(value);
+    }
+}
+
+/// The profile and target everything in a build shares.
+#[derive(Debug, Clone)]
+pub struct BuildConfig {
+    pub profile: Profile,
+    pub target: Target,
+    /// Where everything gets built, see [`Workspace::build_directory`].
+    pub build_directory: PathBuf,
+}
+
+impl BuildConfig {
+    pub fn new(workspace: &Workspace, profile: Profile, target: Target) -> Self {
+        return Self {
+            build_directory: workspace.build_directory(&target, &profile.name),
+            profile,
+            target,
+        };
+    }
+}
+
+pub const SOURCE_EXTENSIONS: &[&str] = &["c", "cpp", "cxx", "c++", "cc", "s", "asm"];
+
+/// Objects go in their own directory, so they can't clash with executables that have no extension.
+pub fn compiled_path(settings: &PackageSettings, package_name: &str) -> PathBuf {
+    return settings.build_directory.join(OBJECT_DIR).join(package_name);
+}
+
+pub fn object_path(
+    compiled: &Path,
+    source: &str,
+    compiler: &dyn CompilerBackend,
+    extra_options: &ExtraCompileOptions,
+) -> PathBuf {
+    return compiled
+        .join(source)
+        .with_extension(compiler.get_object_suffix(extra_options));
+}
+
+/// Everything needed to compile a package's sources.
+#[derive(Debug, Clone)]
+pub struct PackageSettings {
+    pub build_directory: PathBuf,
+    pub extra_options: ExtraCompileOptions,
+    pub include_directories: Vec<String>,
+    pub defines: Vec<String>,
+}
+
+pub fn package_settings(
+    workspace: &Workspace,
+    index: usize,
+    config: &BuildConfig,
+) -> Result<PackageSettings, BuildError> {
+    let package = workspace.package(index);
+    let profile = &config.profile;
+    let target_settings = workspace.target_settings(&config.target);
+
+    let extra_options = ExtraCompileOptions {
+        opt_level: profile.opt_level,
+        generate_debug: profile.debug,
+        lto: package.lto || profile.lto,
+        target: config.target.clone(),
+        sysroot: target_settings
+            .sysroot
+            .map(|it| it.to_str().unwrap().to_string()),
+        linker: target_settings.linker,
+        c_standard: package.c_standard.or(profile.c_standard),
+        cpp_standard: package.cpp_standard.or(profile.cpp_standard),
+        gnu_extensions: package.gnu_extensions.or(profile.gnu_extensions),
+        cpp_stdlib: workspace.cpp_stdlib,
+        cpp_runtime: workspace.cpp_runtime,
+        cflags: package.build.cflags.clone(),
+        cxxflags: package.build.cxxflags.clone(),
+        asflags: package.build.asflags.clone(),
+        nasmflags: package.build.nasmflags.clone(),
+        ldflags: package.build.ldflags.clone(),
+    };
+
+    let build_directory = config.build_directory.clone();
+
+    let mut include_directories: Vec<String> = workspace
+        .include_directories(index)?
+        .iter()
+        .map(|it| it.to_str().unwrap().to_string())
+        .collect();
+
+    for cargo_crate in header_crates(workspace, index)? {
+        let directory = cargo_crate
+            .header_directory(&cargo_target_directory(&build_directory))
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        if !include_directories.contains(&directory) {
+            include_directories.push(directory);
+        }
+    }
+
+    let mut defines = package.build.defines.clone();
+
+    defines.extend(profile.all_defines());
+
+    defines.extend(package.enabled_features.iter().map(|it| feature_define(it)));
+
+    return Ok(PackageSettings {
+        build_directory,
+        extra_options,
+        include_directories,
+        defines,
+    });
+}
+
+pub(crate) fn cargo_target_directory(build_directory: &Path) -> PathBuf {
+    return build_directory.join(CARGO_DIR);
+}
+
+/// The Rust crates with generated headers the package at `index` can include. Like
+/// public include directories, these come from everything it depends on.
+pub(crate) fn header_crates(
+    workspace: &Workspace,
+    index: usize,
+) -> Result<Vec<&CargoCrate>, BuildError> {
+    let mut crates = vec![];
+
+    for dependency in workspace.dependency_order(&[index])? {
+        for cargo_crate in workspace.cargo_dependencies(dependency) {
+            if cargo_crate.header.is_some() {
+                crates.push(cargo_crate);
+            }
+        }
+    }
+
+    return Ok(crates);
+}
+
+/// Runs an action, unless it's up to date.
+fn run_action(action: &Action, compiler: &dyn CompilerBackend) -> io::Result<()> {
+    // Relinking when nothing changed would bump the mtime, and relink everything depending on us.
+    if action.up_to_date()? {
+        return Ok(());
+    }
+
+    for output in &action.outputs {
+        fs::create_dir_all(Path::new(output).parent().unwrap())?;
+    }
+
+    let output = action.output();
+
+    match &action.kind {
+        ActionKind::Compile { source, settings } => {
+            compiler.compile_object(
+                source,
+                output,
+                &settings.include_directories,
+                &settings.defines,
+                &settings.extra_options,
+                false,
+            )?;
+        }
+        ActionKind::Archive {
+            objects,
+            extra_options,
+        } => {
+            // Archivers add to existing archives, which would keep objects of deleted sources around.
+            if fs::exists(output)? {
+                fs::remove_file(output)?;
+            }
+
+            compiler.archive_objects(objects, output, extra_options)?;
+        }
+        ActionKind::Link {
+            objects,
+            libraries,
+            dynamic_library,
+            extra_options,
+        } => {
+            compiler.link_objects(
+                objects,
+                output,
+                &libraries.lib_paths,
+                &libraries.dynamic_libs,
+                &libraries.static_libs,
+                *dynamic_library,
+                libraries.cpp,
+                extra_options,
+            )?;
+        }
+        ActionKind::Cargo {
+            cargo_crate,
+            cargo,
+            target,
+            profile,
+            target_dir,
+        } => {
+            cargo_crate.build(cargo, target, profile, target_dir)?;
+
+            // The rest are copies of what Cargo built.
+            for copy in &action.outputs[1..] {
+                fs::copy(output, copy)?;
+            }
+        }
+        ActionKind::Header {
+            cargo_crate,
+            tool,
+            target_dir,
+        } => {
+            cargo_crate.generate_header(tool, target_dir)?;
+        }
+    }
+
+    action.fingerprint().write(output)?;
+
+    return Ok(());
+}
+
+/// Runs every action of the plan that isn't up to date, `jobs` at a time.
+pub fn execute(plan: &BuildPlan, compiler: &dyn CompilerBackend, jobs: usize) -> io::Result<()> {
+    let mut scheduler = Scheduler::new();
+
+    for action in &plan.actions {
+        scheduler.add(&action.dependencies, move || run_action(action, compiler));
+    }
+
+    return scheduler.run(jobs);
+}
+
+/// Plans and builds `roots` and all of their dependencies.
+pub fn build(
+    workspace: &Workspace,
+    compiler: &dyn CompilerBackend,
+    config: &BuildConfig,
+    roots: &[usize],
+    jobs: usize,
+) -> Result<BuildPlan, BuildError> {
+    let plan = BuildPlan::new(workspace, compiler, config, roots)?;
+
+    execute(&plan, compiler, jobs)?;
+
+    return Ok(plan);
+}
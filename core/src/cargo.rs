@@ -0,0 +1,440 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::Deserialize;
+use which::which;
+
+use crate::{
+    bindings::scan_crate,
+    compiler_backend::{OptLevel, run_tool},
+    profile::Profile,
+    project::WorkspaceError,
+    target::{ObjectFormat, Target},
+};
+
+/// Which kind of C-compatible library a crate builds. `staticlib` wins if it builds both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrateKind {
+    Static,
+    Dynamic,
+}
+
+/// How to generate a C header for a Rust crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderGenerator {
+    /// cbindgen if it's installed, the built-in scanner otherwise.
+    Auto,
+    Cbindgen,
+    /// Only finds `#[no_mangle] extern "C"` functions and statics, and the
+    /// `#[repr(...)]` types and `pub type` aliases declared next to them.
+    Builtin,
+}
+
+/// A [`HeaderGenerator`] with the tool looked up.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderTool {
+    Cbindgen(String),
+    Builtin,
+}
+
+impl HeaderGenerator {
+    pub fn find_tool(self) -> std::io::Result<HeaderTool> {
+        let cbindgen = || which("cbindgen").map(|it| it.to_str().unwrap().to_string());
+
+        return match self {
+            HeaderGenerator::Auto => Ok(cbindgen()
+                .map(HeaderTool::Cbindgen)
+                .unwrap_or(HeaderTool::Builtin)),
+            HeaderGenerator::Cbindgen => cbindgen().map(HeaderTool::Cbindgen).map_err(|_| {
+                std::io::Error::other("Can't generate headers with cbindgen, it isn't installed")
+            }),
+            HeaderGenerator::Builtin => Ok(HeaderTool::Builtin),
+        };
+    }
+}
+
+/// A Rust crate built with Cargo and linked into a package, from a `cargo = "<path>"` dependency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CargoCrate {
+    /// Absolute path of the directory with the crate's Cargo.toml.
+    pub manifest_dir: PathBuf,
+    /// The library name, with dashes turned into underscores like Cargo does.
+    pub lib_name: String,
+    pub kind: CrateKind,
+    /// Cargo features to enable.
+    pub features: Vec<String>,
+    pub default_features: bool,
+    /// Generates `<lib_name>.h` for the packages depending on it, if set.
+    pub header: Option<HeaderGenerator>,
+}
+
+#[derive(Deserialize)]
+struct CargoManifest {
+    package: Option<CargoPackage>,
+    lib: Option<CargoLib>,
+}
+
+#[derive(Deserialize)]
+struct CargoPackage {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct CargoLib {
+    name: Option<String>,
+    #[serde(default, rename = "crate-type")]
+    crate_type: Vec<String>,
+}
+
+impl CargoCrate {
+    pub fn load(
+        manifest_dir: &Path,
+        features: Vec<String>,
+        default_features: bool,
+        header: Option<HeaderGenerator>,
+    ) -> Result<Self, WorkspaceError> {
+        let manifest_path = manifest_dir.join("Cargo.toml");
+
+        let invalid = |message: &str| {
+            return WorkspaceError::InvalidCargoCrate(manifest_path.clone(), message.to_string());
+        };
+
+        if !manifest_path.exists() {
+            return Err(WorkspaceError::PackageNotFound(manifest_path));
+        }
+
+        let manifest: CargoManifest = toml::from_str(&fs::read_to_string(&manifest_path)?)?;
+
+        let name = manifest
+            .lib
+            .as_ref()
+            .and_then(|it| it.name.clone())
+            .or_else(|| manifest.package.as_ref().map(|it| it.name.clone()))
+            .ok_or_else(|| invalid("expected a [package] or a named [lib]"))?;
+
+        let crate_types = manifest.lib.map(|it| it.crate_type).unwrap_or_default();
+
+        let kind = if crate_types.iter().any(|it| it == "staticlib") {
+            CrateKind::Static
+        } else if crate_types.iter().any(|it| it == "cdylib") {
+            CrateKind::Dynamic
+        } else {
+            return Err(invalid(
+                "lib.crate-type needs to contain staticlib or cdylib",
+            ));
+        };
+
+        return Ok(Self {
+            manifest_dir: manifest_dir.to_path_buf(),
+            lib_name: name.replace('-', "_"),
+            kind,
+            features,
+            default_features,
+            header,
+        });
+    }
+
+    /// Adds the features another package asked for, so the crate is only built once.
+    pub fn unify(&mut self, other: &CargoCrate) {
+        for feature in &other.features {
+            if !self.features.contains(feature) {
+                self.features.push(feature.clone());
+            }
+        }
+
+        self.default_features |= other.default_features;
+        self.header = self.header.or(other.header);
+    }
+
+    pub fn build_command(
+        &self,
+        cargo: &str,
+        target: &Target,
+        profile: &Profile,
+        target_dir: &Path,
+    ) -> Vec<String> {
+        let mut args = vec![
+            cargo.to_string(),
+            "build".into(),
+            "--offline".into(),
+            "--lib".into(),
+            "--manifest-path".into(),
+            self.manifest_dir
+                .join("Cargo.toml")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            "--target-dir".into(),
+            target_dir.to_str().unwrap().to_string(),
+            "--target".into(),
+            target.rust_triple(),
+            "--profile".into(),
+            cargo_profile(profile).into(),
+        ];
+
+        if !self.default_features {
+            args.push("--no-default-features".into());
+        }
+
+        if !self.features.is_empty() {
+            args.extend(["--features".into(), self.features.join(",")]);
+        }
+
+        return args;
+    }
+
+    pub fn build(
+        &self,
+        cargo: &str,
+        target: &Target,
+        profile: &Profile,
+        target_dir: &Path,
+    ) -> std::io::Result<()> {
+        let args = self.build_command(cargo, target, profile, target_dir);
+
+        let status = run_tool(Command::new(&args[0]).args(&args[1..]))?;
+
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "Cargo exited with status {}",
+                status
+            )));
+        }
+
+        return Ok(());
+    }
+
+    /// The directory the generated header goes in, which dependents get as an include directory.
+    pub fn header_directory(&self, target_dir: &Path) -> PathBuf {
+        return target_dir.join("include").join(&self.lib_name);
+    }
+
+    pub fn header_path(&self, target_dir: &Path) -> PathBuf {
+        return self
+            .header_directory(target_dir)
+            .join(format!("{}.h", self.lib_name));
+    }
+
+    /// The depfile listing the sources the header was generated from.
+    pub fn header_depfile_path(&self, target_dir: &Path) -> PathBuf {
+        return self.header_path(target_dir).with_extension("d");
+    }
+
+    /// For the built-in scanner, this is only used to tell if the header needs regenerating.
+    pub fn header_command(&self, tool: &HeaderTool, target_dir: &Path) -> Vec<String> {
+        let header = self.header_path(target_dir).to_str().unwrap().to_string();
+
+        let HeaderTool::Cbindgen(cbindgen) = tool else {
+            return vec![
+                "builtin".into(),
+                env!("CARGO_PKG_VERSION").into(),
+                self.manifest_dir.to_str().unwrap().to_string(),
+                header,
+            ];
+        };
+
+        let mut args = vec![
+            cbindgen.clone(),
+            "--quiet".into(),
+            "--output".into(),
+            header,
+            "--depfile".into(),
+            self.header_depfile_path(target_dir)
+                .to_str()
+                .unwrap()
+                .to_string(),
+        ];
+
+        // The crate's own config can pick C++ instead.
+        let config = self.manifest_dir.join("cbindgen.toml");
+
+        if config.exists() {
+            args.extend(["--config".into(), config.to_str().unwrap().to_string()]);
+        } else {
+            args.extend(["--lang".into(), "c".into()]);
+        }
+
+        args.push(self.manifest_dir.to_str().unwrap().to_string());
+
+        return args;
+    }
+
+    pub fn generate_header(&self, tool: &HeaderTool, target_dir: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(self.header_directory(target_dir))?;
+
+        if let HeaderTool::Cbindgen(_) = tool {
+            let args = self.header_command(tool, target_dir);
+
+            let status = run_tool(Command::new(&args[0]).args(&args[1..]))?;
+
+            if !status.success() {
+                return Err(std::io::Error::other(format!(
+                    "cbindgen exited with status {}",
+                    status
+                )));
+            }
+
+            return Ok(());
+        }
+
+        let header = self.header_path(target_dir);
+
+        let (exports, files) = scan_crate(&self.manifest_dir.join("src"))?;
+
+        fs::write(&header, exports.header(&self.lib_name))?;
+
+        let escape = |path: &str| path.replace(' ', "\\ ");
+
+        fs::write(
+            self.header_depfile_path(target_dir),
+            format!(
+                "{}: {}\n",
+                escape(header.to_str().unwrap()),
+                files
+                    .iter()
+                    .map(|it| escape(it))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+        )?;
+
+        return Ok(());
+    }
+
+    /// Where Cargo puts the library.
+    pub fn artifact_path(&self, target: &Target, profile: &Profile, target_dir: &Path) -> PathBuf {
+        return target_dir
+            .join(target.rust_triple())
+            .join(cargo_output_directory(profile))
+            .join(self.file_name(target));
+    }
+
+    /// Cargo writes a depfile next to the library, listing every source it was built from.
+    pub fn depfile_path(&self, target: &Target, profile: &Profile, target_dir: &Path) -> PathBuf {
+        return self
+            .artifact_path(target, profile, target_dir)
+            .with_extension("d");
+    }
+
+    pub fn file_name(&self, target: &Target) -> String {
+        return match self.kind {
+            CrateKind::Static => format!(
+                "{}{}.{}",
+                target.library_prefix(),
+                self.lib_name,
+                target.static_suffix()
+            ),
+            // Rust names DLLs without a prefix, even with the GNU toolchain.
+            CrateKind::Dynamic if target.is_windows() => format!("{}.dll", self.lib_name),
+            CrateKind::Dynamic => format!("lib{}.{}", self.lib_name, target.dynamic_suffix()),
+        };
+    }
+
+    /// What to give the linker for the library. Static ones are named by file, so
+    /// a cdylib next to it can't get picked instead.
+    pub fn link_name(&self, target: &Target) -> String {
+        if self.kind == CrateKind::Static && target.object_format == ObjectFormat::Elf {
+            return format!(":{}", self.file_name(target));
+        }
+
+        return self.lib_name.clone();
+    }
+}
+
+/// Cargo only knows about `dev` and `release`, unless the crate defines more. Our
+/// other profiles get whichever of the two is closest.
+pub fn cargo_profile(profile: &Profile) -> &'static str {
+    if profile.name == Profile::DEV {
+        return "dev";
+    }
+
+    if profile.name == Profile::RELEASE || profile.opt_level != OptLevel::Zero {
+        return "release";
+    }
+
+    return "dev";
+}
+
+fn cargo_output_directory(profile: &Profile) -> &'static str {
+    return match cargo_profile(profile) {
+        "dev" => "debug",
+        other => other,
+    };
+}
+
+/// System libraries a Rust staticlib needs on `target`. A cdylib links them itself.
+pub fn rust_system_libs(target: &Target) -> &'static [&'static str] {
+    return match target.cfg_os() {
+        "linux" => &["gcc_s", "util", "rt", "pthread", "m", "dl", "c"],
+        // Bionic has no librt or libutil, and the NDK has no libgcc_s.
+        "android" => &["dl", "log", "unwind", "m", "c"],
+        "freebsd" | "netbsd" | "openbsd" | "dragonfly" => &["execinfo", "pthread", "m", "c"],
+        "macos" | "ios" | "tvos" | "watchos" | "visionos" => &["System", "c", "m"],
+        "windows" if target.is_msvc() => &[
+            "kernel32", "advapi32", "bcrypt", "ntdll", "userenv", "ws2_32", "msvcrt",
+        ],
+        "windows" => &[
+            "kernel32", "advapi32", "bcrypt", "ntdll", "userenv", "ws2_32", "gcc_eh", "pthread",
+        ],
+        _ => &[],
+    };
+}
+
+/// The `cargo` running us if there is one, so `cargo run` uses the same toolchain.
+pub fn find_cargo() -> std::io::Result<PathBuf> {
+    if let Some(cargo) = std::env::var_os("CARGO") {
+        return Ok(cargo.into());
+    }
+
+    return which("cargo")
+        .map_err(|_| std::io::Error::other("Can't build Rust crates, Cargo isn't installed"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system_libs(triple: &str) -> &'static [&'static str] {
+        return rust_system_libs(&Target::parse(triple).unwrap());
+    }
+
+    #[test]
+    fn system_libs_for_each_os() {
+        assert_eq!(
+            system_libs("x86_64-unknown-linux-gnu"),
+            ["gcc_s", "util", "rt", "pthread", "m", "dl", "c"]
+        );
+        assert_eq!(
+            system_libs("aarch64-linux-android"),
+            ["dl", "log", "unwind", "m", "c"]
+        );
+        assert_eq!(
+            system_libs("armv7-linux-androideabi"),
+            ["dl", "log", "unwind", "m", "c"]
+        );
+        assert_eq!(
+            system_libs("x86_64-unknown-freebsd"),
+            ["execinfo", "pthread", "m", "c"]
+        );
+        assert_eq!(system_libs("aarch64-apple-darwin"), ["System", "c", "m"]);
+        assert_eq!(system_libs("aarch64-apple-ios"), ["System", "c", "m"]);
+        assert_eq!(
+            system_libs("x86_64-pc-windows-msvc"),
+            [
+                "kernel32", "advapi32", "bcrypt", "ntdll", "userenv", "ws2_32", "msvcrt"
+            ]
+        );
+        assert_eq!(
+            system_libs("x86_64-pc-windows-gnu"),
+            [
+                "kernel32", "advapi32", "bcrypt", "ntdll", "userenv", "ws2_32", "gcc_eh", "pthread"
+            ]
+        );
+        assert!(system_libs("thumbv7em-none-eabihf").is_empty());
+        assert!(system_libs("wasm32-unknown-unknown").is_empty());
+    }
+}
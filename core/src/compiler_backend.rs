@@ -1,8 +1,17 @@
-use std::process::Command;
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{IsTerminal, Write},
+    path::Path,
+    process::{Command, ExitStatus},
+    str::FromStr,
+    time::UNIX_EPOCH,
+};
 
+use serde::Deserialize;
 use which::which;
 
-use crate::{DEFAULT_TARGET, target_is_msvc, target_is_windows};
+use crate::target::{ObjectFormat, Target};
 
 /// I don't know if this is any better than just keeping a string...
 /// Different compilers will have different level types, but there's some commonality.
@@ -20,6 +29,19 @@ pub enum OptLevel {
 }
 
 impl OptLevel {
+    pub fn parse(value: &str) -> Option<Self> {
+        return match value {
+            "0" => Some(Self::Zero),
+            "1" => Some(Self::One),
+            "2" => Some(Self::Two),
+            "3" => Some(Self::Three),
+            "fast" => Some(Self::Fast),
+            "s" => Some(Self::Size),
+            "z" => Some(Self::SizeAggressive),
+            _ => None,
+        };
+    }
+
     pub fn string(self) -> &'static str {
         return match self {
             Self::Zero => "0",
@@ -33,13 +55,217 @@ impl OptLevel {
     }
 }
 
+/// Accepts both `opt_level = 3` and `opt_level = "s"`, like Cargo does.
+impl<'de> Deserialize<'de> for OptLevel {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Int(i64),
+            Str(String),
+        }
+
+        let value = match Raw::deserialize(deserializer)? {
+            Raw::Int(value) => value.to_string(),
+            Raw::Str(value) => value,
+        };
+
+        return Self::parse(&value).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid opt_level {value:?}, expected one of 0, 1, 2, 3, \"fast\", \"s\" or \"z\""
+            ))
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Language {
+    C,
+    Cpp,
+    /// `.s`, GAS syntax, assembled as is.
+    Assembly,
+    /// `.S`, GAS syntax, run through the preprocessor first.
+    PreprocessedAssembly,
+    /// `.asm`, Intel syntax, assembled by NASM or YASM.
+    Nasm,
+}
+
+pub const CXX_EXTENSIONS: &[&str] = &["cpp", "cxx", "c++", "cc"];
+
+impl Language {
+    /// Infers the language from the extension. Only `.s` and `.S` care about case.
+    pub fn of_source(source_path: &str) -> Option<Self> {
+        let raw_extension = Path::new(source_path)
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+
+        match raw_extension {
+            "s" => return Some(Self::Assembly),
+            "S" => return Some(Self::PreprocessedAssembly),
+            _ => {}
+        }
+
+        let extension = source_extension(source_path);
+
+        if extension == "asm" {
+            return Some(Self::Nasm);
+        }
+
+        if extension == "c" {
+            return Some(Self::C);
+        }
+
+        if CXX_EXTENSIONS.contains(&extension.as_str()) {
+            return Some(Self::Cpp);
+        }
+
+        return None;
+    }
+
+    fn versions(self) -> &'static [&'static str] {
+        return match self {
+            Self::C => &["89", "99", "11", "17", "23"],
+            Self::Cpp => &["98", "03", "11", "14", "17", "20", "23", "26"],
+            _ => &[],
+        };
+    }
+}
+
+/// Which C++ standard library to compile and link against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+pub enum CppStdlib {
+    #[serde(rename = "libc++")]
+    LibCpp,
+    #[serde(rename = "libstdc++")]
+    LibStdCpp,
+}
+
+impl CppStdlib {
+    pub fn string(self) -> &'static str {
+        return match self {
+            Self::LibCpp => "libc++",
+            Self::LibStdCpp => "libstdc++",
+        };
+    }
+}
+
+/// How the C++ runtime gets linked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CppRuntime {
+    #[default]
+    Shared,
+    Static,
+}
+
+/// A language standard, like `c11` or `gnu++20`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LanguageStandard {
+    pub language: Language,
+    pub version: &'static str,
+    /// Whether it was given as a `gnu` variant.
+    pub gnu: bool,
+}
+
+impl LanguageStandard {
+    pub fn parse(value: &str) -> Option<Self> {
+        // Longest prefixes first, `c` would match `c++` too.
+        let (language, gnu, version) = [
+            ("c++", Language::Cpp, false),
+            ("gnu++", Language::Cpp, true),
+            ("gnu", Language::C, true),
+            ("c", Language::C, false),
+        ]
+        .into_iter()
+        .find_map(|(prefix, language, gnu)| {
+            return value
+                .strip_prefix(prefix)
+                .map(|version| (language, gnu, version));
+        })?;
+
+        let version = language.versions().iter().find(|it| **it == version)?;
+
+        return Some(Self {
+            language,
+            version,
+            gnu,
+        });
+    }
+
+    /// What GCC and Clang use when no standard is given, C17 and C++17, without
+    /// GNU extensions.
+    pub fn default_for(language: Language) -> Option<Self> {
+        let version = match language {
+            Language::C | Language::Cpp => "17",
+            _ => return None,
+        };
+
+        return Some(Self {
+            language,
+            version,
+            gnu: false,
+        });
+    }
+
+    /// The `-std=` flag, with GNU extensions if `gnu` is set.
+    pub fn flag(&self, gnu: bool) -> String {
+        let prefix = match (self.language, gnu) {
+            (Language::Cpp, false) => "c++",
+            (Language::Cpp, true) => "gnu++",
+            // Only C and C++ standards get parsed.
+            (_, false) => "c",
+            (_, true) => "gnu",
+        };
+
+        return format!("-std={prefix}{}", self.version);
+    }
+}
+
+impl<'de> Deserialize<'de> for LanguageStandard {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+
+        return Self::parse(&value).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid language standard {value:?}, expected something like \"c11\", \"gnu17\" or \"c++20\""
+            ))
+        });
+    }
+}
+
 /// For future-proofing, user-changable compiler-specific flags should be added to this struct,
 /// so if we add more compiler backends in the future we'll have an easier time converting.
+#[derive(Debug, Clone)]
 pub struct ExtraCompileOptions {
     pub opt_level: OptLevel,
     pub generate_debug: bool,
     pub lto: bool,
-    pub target: String,
+    pub target: Target,
+    pub sysroot: Option<String>,
+    /// Linker for clang to use instead of its default one, like `lld`.
+    pub linker: Option<String>,
+    pub c_standard: Option<LanguageStandard>,
+    pub cpp_standard: Option<LanguageStandard>,
+    /// Overrides whether the standards above get their `gnu` variant. Without one,
+    /// the compiler's default standard is used.
+    pub gnu_extensions: Option<bool>,
+    /// The compiler's default one if not set. GCC only has libstdc++, so it ignores this.
+    pub cpp_stdlib: Option<CppStdlib>,
+    pub cpp_runtime: CppRuntime,
+    pub cflags: Vec<String>,
+    pub cxxflags: Vec<String>,
+    /// Passed when assembling `.s` and `.S` files.
+    pub asflags: Vec<String>,
+    /// Passed when assembling `.asm` files.
+    pub nasmflags: Vec<String>,
+    pub ldflags: Vec<String>,
 }
 
 impl Default for ExtraCompileOptions {
@@ -48,72 +274,232 @@ impl Default for ExtraCompileOptions {
             opt_level: OptLevel::default(),
             generate_debug: true,
             lto: false,
-            target: DEFAULT_TARGET.into(),
+            target: Target::host(),
+            sysroot: None,
+            linker: None,
+            c_standard: None,
+            cpp_standard: None,
+            gnu_extensions: None,
+            cpp_stdlib: None,
+            cpp_runtime: CppRuntime::default(),
+            cflags: vec![],
+            cxxflags: vec![],
+            asflags: vec![],
+            nasmflags: vec![],
+            ldflags: vec![],
         };
     }
 }
 
-/// Compiler paths are cached here so we don't need to locate it every time we
-/// try to invoke it or generate a compile command.
-pub struct ClangCompilerBackend {
-    compiler_path: String,
-    lib_path: String,
-    ar_path: String,
+fn source_extension(source_path: &str) -> String {
+    return Path::new(source_path)
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or_default()
+        .to_lowercase();
 }
 
-impl ClangCompilerBackend {
-    pub fn new() -> which::Result<Self> {
-        return Ok(Self {
-            compiler_path: which("clang")?.to_str().unwrap().to_string(),
-            lib_path: which("llvm-lib")?.to_str().unwrap().to_string(),
-            ar_path: which("llvm-ar")?.to_str().unwrap().to_string(),
-        });
+/// Identifies a tool by where it really lives, its size and its modification time.
+/// Asking it for its version would be more precise, but means spawning it every build.
+fn tool_identity(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+    let Ok(metadata) = fs::metadata(&path) else {
+        return path.to_str().unwrap().to_string();
+    };
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|it| it.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |it| it.as_nanos());
+
+    return format!("{} {} {}", path.to_str().unwrap(), metadata.len(), modified);
+}
+
+/// Tools can run in parallel, so their output is captured and written all at
+/// once, to keep diagnostics from different files from interleaving.
+pub(crate) fn run_tool(command: &mut Command) -> std::io::Result<ExitStatus> {
+    let output = command.output()?;
+
+    let mut stderr = std::io::stderr().lock();
+
+    stderr.write_all(&output.stdout)?;
+    stderr.write_all(&output.stderr)?;
+    stderr.flush()?;
+
+    return Ok(output.status);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AssemblerKind {
+    Nasm,
+    Yasm,
+}
+
+/// Assembles `.asm` files, NASM if it's installed, otherwise YASM.
+struct Assembler {
+    kind: AssemblerKind,
+    path: String,
+}
+
+impl Assembler {
+    fn find() -> Option<Self> {
+        return [("nasm", AssemblerKind::Nasm), ("yasm", AssemblerKind::Yasm)]
+            .into_iter()
+            .find_map(|(name, kind)| {
+                return which(name).ok().map(|path| Self {
+                    kind,
+                    path: path.to_str().unwrap().to_string(),
+                });
+            });
     }
 
-    pub fn get_object_suffix(&self, extra_options: &ExtraCompileOptions) -> &str {
-        return if target_is_msvc(&extra_options.target) {
-            "obj"
-        } else {
-            "o"
+    fn object_format(target: &Target) -> std::io::Result<String> {
+        let format = match target.object_format {
+            ObjectFormat::Elf => "elf",
+            ObjectFormat::MachO => "macho",
+            ObjectFormat::Coff => "win",
+            ObjectFormat::Wasm => {
+                return Err(std::io::Error::other(format!(
+                    "Can't assemble .asm files for {target}"
+                )));
+            }
         };
+
+        return Ok(format!("{format}{}", target.pointer_width()));
     }
 
-    pub fn get_static_suffix(&self, extra_options: &ExtraCompileOptions) -> &str {
-        return if target_is_msvc(&extra_options.target) {
-            "lib"
-        } else {
-            "a"
-        };
+    fn command(
+        &self,
+        source_path: &str,
+        output_path: &str,
+        include_directories: &[String],
+        defines: &[String],
+        extra_options: &ExtraCompileOptions,
+    ) -> std::io::Result<Vec<String>> {
+        let target = &extra_options.target;
+
+        let mut args = vec![self.path.clone()];
+
+        args.extend(["-f".into(), Self::object_format(target)?]);
+
+        args.extend(["-o".into(), output_path.into()]);
+
+        // Older versions of NASM only work with a trailing slash.
+        args.extend(include_directories.iter().map(|it| format!("-I{it}/")));
+
+        args.extend(defines.iter().map(|it| format!("-D{it}")));
+
+        if extra_options.generate_debug {
+            let debug_format = match (self.kind, target.object_format) {
+                (_, ObjectFormat::Coff) => "cv8",
+                (AssemblerKind::Nasm, _) => "dwarf",
+                (AssemblerKind::Yasm, _) => "dwarf2",
+            };
+
+            match self.kind {
+                AssemblerKind::Nasm => args.extend(["-g".into(), "-F".into(), debug_format.into()]),
+                AssemblerKind::Yasm => args.extend(["-g".into(), debug_format.into()]),
+            }
+        }
+
+        // YASM can only write dependencies to stdout, so we write its depfile ourselves.
+        if self.kind == AssemblerKind::Nasm {
+            let depfile = Path::new(output_path).with_extension("d");
+
+            args.extend([
+                "-MD".into(),
+                depfile.to_str().unwrap().to_string(),
+                "-MT".into(),
+                output_path.into(),
+            ]);
+        }
+
+        args.extend(extra_options.nasmflags.iter().cloned());
+
+        args.push(source_path.into());
+
+        return Ok(args);
     }
+}
 
-    pub fn get_dynamic_suffix(&self, extra_options: &ExtraCompileOptions) -> &str {
-        return if target_is_windows(&extra_options.target) {
-            "dll"
-        } else {
-            "so"
+/// Which compiler builds everything, set with `build.compiler` or `--compiler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CompilerKind {
+    #[default]
+    Clang,
+    Gcc,
+}
+
+impl CompilerKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        return match value {
+            "clang" => Some(Self::Clang),
+            "gcc" => Some(Self::Gcc),
+            _ => None,
         };
     }
 
-    pub fn get_library_prefix(&self, extra_options: &ExtraCompileOptions) -> &str {
-        return if target_is_msvc(&extra_options.target) {
-            ""
-        } else {
-            "lib"
+    pub fn string(self) -> &'static str {
+        return match self {
+            Self::Clang => "clang",
+            Self::Gcc => "gcc",
         };
     }
 
-    pub fn get_executable_suffix(&self, extra_options: &ExtraCompileOptions) -> &str {
-        return if target_is_windows(&extra_options.target) {
-            "exe"
-        } else {
-            "out"
-        };
+    /// Finds the compiler's tools. Clang can build for any target, GCC needs a cross
+    /// compiler for anything but the host.
+    pub fn backend(self, target: &Target) -> std::io::Result<Box<dyn CompilerBackend>> {
+        return Ok(match self {
+            Self::Clang => Box::new(
+                ClangCompilerBackend::new()
+                    .map_err(|e| std::io::Error::other(format!("Can't find Clang ({e})")))?,
+            ),
+            Self::Gcc => Box::new(GccCompilerBackend::new(target)?),
+        });
+    }
+}
+
+impl FromStr for CompilerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return Self::parse(s)
+            .ok_or_else(|| format!("Unknown compiler {s:?}, expected clang or gcc"));
+    }
+}
+
+/// Everything a build needs from a compiler. The suffixes default to what's usual for the target.
+pub trait CompilerBackend: Sync {
+    /// Changes whenever the compiler binary does, so upgrading it causes a rebuild.
+    fn identity(&self) -> &str;
+
+    fn get_object_suffix(&self, extra_options: &ExtraCompileOptions) -> &str {
+        return extra_options.target.object_suffix();
+    }
+
+    fn get_static_suffix(&self, extra_options: &ExtraCompileOptions) -> &str {
+        return extra_options.target.static_suffix();
+    }
+
+    fn get_dynamic_suffix(&self, extra_options: &ExtraCompileOptions) -> &str {
+        return extra_options.target.dynamic_suffix();
+    }
+
+    fn get_library_prefix(&self, extra_options: &ExtraCompileOptions) -> &str {
+        return extra_options.target.library_prefix();
+    }
+
+    fn get_executable_suffix(&self, extra_options: &ExtraCompileOptions) -> Option<&str> {
+        return extra_options.target.executable_suffix();
     }
 
     /// If gen_compile_commands is set, the command isn't run, and, instead,
     /// it returns a list of the compile commands. This is used to generate
     /// a compile_commands.json file.
-    pub fn compile_object(
+    fn compile_object(
         &self,
         source_path: &str,
         output_path: &str,
@@ -121,30 +507,417 @@ impl ClangCompilerBackend {
         defines: &[String],
         extra_options: &ExtraCompileOptions,
         gen_compile_commands: bool,
-    ) -> std::io::Result<Option<Vec<String>>> {
-        let mut args: Vec<String> = vec![];
+    ) -> std::io::Result<Option<Vec<String>>>;
 
-        args.extend(["-c".into(), source_path.into()]);
+    /// The full archiver command line, starting with the archiver itself.
+    fn archive_command(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        extra_options: &ExtraCompileOptions,
+    ) -> Vec<String>;
 
-        args.extend(["-o".into(), output_path.into()]);
+    fn archive_objects(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        extra_options: &ExtraCompileOptions,
+    ) -> std::io::Result<()> {
+        let args = self.archive_command(object_paths, output_path, extra_options);
 
-        args.extend(include_directories.iter().map(|it| format!("-I{it}")));
+        let status = run_tool(Command::new(&args[0]).args(&args[1..]))?;
 
-        args.extend(defines.iter().map(|it| format!("-D{it}")));
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "Archiver exited with status {}",
+                status
+            )));
+        }
 
-        args.push(format!("-O{}", extra_options.opt_level.string()));
+        return Ok(());
+    }
 
-        if extra_options.generate_debug {
-            args.push("-ggdb3".into());
+    /// The full linker command line, starting with the compiler driver. When any
+    /// of the objects or static libraries has C++ in it, `cpp` needs to be set, so
+    /// the C++ driver links in the runtime. Static libraries are paths to the archives,
+    /// and get linked whole.
+    #[allow(clippy::too_many_arguments)]
+    fn link_command(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        lib_paths: &[String],
+        dynamic_libs: &[String],
+        static_libs: &[String],
+        dynamic_library: bool,
+        cpp: bool,
+        extra_options: &ExtraCompileOptions,
+    ) -> Vec<String>;
+
+    #[allow(clippy::too_many_arguments)]
+    fn link_objects(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        lib_paths: &[String],
+        dynamic_libs: &[String],
+        static_libs: &[String],
+        dynamic_library: bool,
+        cpp: bool,
+        extra_options: &ExtraCompileOptions,
+    ) -> std::io::Result<()> {
+        let args = self.link_command(
+            object_paths,
+            output_path,
+            lib_paths,
+            dynamic_libs,
+            static_libs,
+            dynamic_library,
+            cpp,
+            extra_options,
+        );
+
+        let status = run_tool(Command::new(&args[0]).args(&args[1..]))?;
+
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "Linker exited with status {}",
+                status
+            )));
         }
 
-        args.push("-MD".into());
+        return Ok(());
+    }
+}
+
+/// GCC and Clang take the same flags, except Clang picks its target with `-target`,
+/// where GCC needs a different compiler for every target.
+fn compile_args(
+    kind: CompilerKind,
+    source_path: &str,
+    output_path: &str,
+    include_directories: &[String],
+    defines: &[String],
+    extra_options: &ExtraCompileOptions,
+) -> Vec<String> {
+    let language = Language::of_source(source_path);
 
-        args.extend(["-target".into(), extra_options.target.clone()]);
+    let mut args: Vec<String> = vec![];
 
-        if !target_is_msvc(&extra_options.target) {
-            args.push("-fPIC".into());
+    // Spelled out, so case-insensitive file systems can't mix the two up.
+    match language {
+        Some(Language::Assembly) => args.extend(["-x".into(), "assembler".into()]),
+        Some(Language::PreprocessedAssembly) => {
+            args.extend(["-x".into(), "assembler-with-cpp".into()])
         }
+        _ => {}
+    }
+
+    args.extend(["-c".into(), source_path.into()]);
+
+    args.extend(["-o".into(), output_path.into()]);
+
+    args.extend(include_directories.iter().map(|it| format!("-I{it}")));
+
+    args.extend(defines.iter().map(|it| format!("-D{it}")));
+
+    args.push(format!("-O{}", extra_options.opt_level.string()));
+
+    if extra_options.generate_debug {
+        args.push("-ggdb3".into());
+    }
+
+    args.push("-MD".into());
+
+    if kind == CompilerKind::Clang {
+        args.extend(["-target".into(), extra_options.target.to_string()]);
+    }
+
+    if let Some(sysroot) = &extra_options.sysroot {
+        args.push(format!("--sysroot={sysroot}"));
+    }
+
+    if !extra_options.target.is_msvc() {
+        args.push("-fPIC".into());
+    }
+
+    let standard = match language {
+        Some(Language::C) => extra_options.c_standard,
+        Some(Language::Cpp) => extra_options.cpp_standard,
+        _ => None,
+    };
+
+    // Turning GNU extensions on or off needs a `-std=`, so use the compiler's default one.
+    let standard = standard.or_else(|| {
+        extra_options.gnu_extensions?;
+
+        return LanguageStandard::default_for(language?);
+    });
+
+    if let Some(standard) = standard {
+        args.push(standard.flag(extra_options.gnu_extensions.unwrap_or(standard.gnu)));
+    }
+
+    if kind == CompilerKind::Clang
+        && language == Some(Language::Cpp)
+        && let Some(stdlib) = extra_options.cpp_stdlib
+    {
+        args.push(format!("-stdlib={}", stdlib.string()));
+    }
+
+    match language {
+        Some(Language::C) => args.extend(extra_options.cflags.iter().cloned()),
+        Some(Language::Cpp) => args.extend(extra_options.cxxflags.iter().cloned()),
+        Some(Language::Assembly | Language::PreprocessedAssembly) => {
+            args.extend(extra_options.asflags.iter().cloned())
+        }
+        Some(Language::Nasm) | None => {}
+    }
+
+    return args;
+}
+
+/// Runs the compiler with `args`, plus `color_flag` when diagnostics end up in a
+/// terminal, since neither compiler colors output it isn't writing to one itself.
+fn run_compiler(
+    name: &str,
+    compiler_path: &str,
+    args: &[String],
+    color_flag: &str,
+    source_path: &str,
+    output_path: &str,
+) -> std::io::Result<()> {
+    let mut command = Command::new(compiler_path);
+
+    command.args(args);
+
+    if std::io::stderr().is_terminal() {
+        command.arg(color_flag);
+    }
+
+    let status = run_tool(&mut command)?;
+
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "{name} exited with status {}",
+            status
+        )));
+    }
+
+    // Not every assembler writes one for `.s` files, and without it we'd rebuild them every time.
+    let depfile = Path::new(output_path).with_extension("d");
+
+    if !fs::exists(&depfile)? {
+        fs::write(depfile, format!("{output_path}: {source_path}\n"))?;
+    }
+
+    return Ok(());
+}
+
+fn assemble_nasm(
+    assembler: Option<&Assembler>,
+    source_path: &str,
+    output_path: &str,
+    include_directories: &[String],
+    defines: &[String],
+    extra_options: &ExtraCompileOptions,
+    gen_compile_commands: bool,
+) -> std::io::Result<Option<Vec<String>>> {
+    let Some(assembler) = assembler else {
+        return Err(std::io::Error::other(format!(
+            "Can't assemble {source_path}, neither NASM nor YASM is installed"
+        )));
+    };
+
+    let args = assembler.command(
+        source_path,
+        output_path,
+        include_directories,
+        defines,
+        extra_options,
+    )?;
+
+    if gen_compile_commands {
+        return Ok(Some(args));
+    }
+
+    let status = run_tool(Command::new(&args[0]).args(&args[1..]))?;
+
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "Assembler exited with status {}",
+            status
+        )));
+    }
+
+    if assembler.kind == AssemblerKind::Yasm {
+        fs::write(
+            Path::new(output_path).with_extension("d"),
+            format!("{output_path}: {source_path}\n"),
+        )?;
+    }
+
+    return Ok(None);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn link_args(
+    kind: CompilerKind,
+    driver: &str,
+    object_paths: &[String],
+    output_path: &str,
+    lib_paths: &[String],
+    dynamic_libs: &[String],
+    static_libs: &[String],
+    dynamic_library: bool,
+    cpp: bool,
+    extra_options: &ExtraCompileOptions,
+) -> Vec<String> {
+    let mut args: Vec<String> = vec![driver.to_string()];
+
+    args.extend(object_paths.iter().cloned());
+
+    args.extend(lib_paths.iter().map(|it| format!("-L{}", it)));
+
+    if extra_options.target.is_msvc() {
+        args.extend(static_libs.iter().cloned());
+    } else if extra_options.target.object_format == ObjectFormat::MachO {
+        // ld64 has no --whole-archive, it takes every archive on its own.
+        args.extend(static_libs.iter().map(|it| format!("-Wl,-force_load,{it}")));
+    } else if !static_libs.is_empty() {
+        args.push("-Wl,--whole-archive".into());
+        args.extend(static_libs.iter().cloned());
+        args.push("-Wl,--no-whole-archive".into());
+    }
+
+    // Static libraries can depend on dynamic ones, so these need to come after.
+    args.extend(dynamic_libs.iter().map(|it| format!("-l{}", it)));
+
+    if dynamic_library {
+        args.push("-shared".into());
+    }
+
+    // Dependencies are placed next to their dependents, so let the loader find them there.
+    if !dynamic_libs.is_empty() && !extra_options.target.is_windows() {
+        if extra_options.target.object_format == ObjectFormat::MachO {
+            args.push("-Wl,-rpath,@loader_path".into());
+        } else {
+            args.push("-Wl,-rpath,$ORIGIN".into());
+        }
+    }
+
+    args.extend(["-o".into(), output_path.into()]);
+
+    if kind == CompilerKind::Clang {
+        args.extend(["-target".into(), extra_options.target.to_string()]);
+    }
+
+    if let Some(sysroot) = &extra_options.sysroot {
+        args.push(format!("--sysroot={sysroot}"));
+    }
+
+    if let Some(linker) = &extra_options.linker {
+        args.push(format!("-fuse-ld={linker}"));
+    }
+
+    if cpp && !extra_options.target.is_msvc() {
+        if kind == CompilerKind::Clang
+            && let Some(stdlib) = extra_options.cpp_stdlib
+        {
+            args.push(format!("-stdlib={}", stdlib.string()));
+        }
+
+        // Clang takes this for libc++ as well.
+        if extra_options.cpp_runtime == CppRuntime::Static {
+            args.push("-static-libstdc++".into());
+        }
+    }
+
+    if extra_options.lto {
+        args.push("-flto".into());
+    }
+
+    args.extend(extra_options.ldflags.iter().cloned());
+
+    return args;
+}
+
+/// Compiler paths are cached here so we don't need to locate it every time we
+/// try to invoke it or generate a compile command.
+pub struct ClangCompilerBackend {
+    compiler_path: String,
+    /// `clang++`, used for linking anything with C++ in it.
+    cxx_compiler_path: String,
+    lib_path: String,
+    ar_path: String,
+    assembler: Option<Assembler>,
+    identity: String,
+}
+
+impl ClangCompilerBackend {
+    pub fn new() -> which::Result<Self> {
+        let compiler_path = which("clang")?;
+
+        let cxx_compiler_path = which("clang++")?;
+
+        let assembler = Assembler::find();
+
+        let mut identity = format!(
+            "{}\n{}",
+            tool_identity(&compiler_path),
+            tool_identity(&cxx_compiler_path)
+        );
+
+        if let Some(assembler) = &assembler {
+            identity.push('\n');
+            identity.push_str(&tool_identity(Path::new(&assembler.path)));
+        }
+
+        return Ok(Self {
+            identity,
+            assembler,
+            compiler_path: compiler_path.to_str().unwrap().to_string(),
+            cxx_compiler_path: cxx_compiler_path.to_str().unwrap().to_string(),
+            lib_path: which("llvm-lib")?.to_str().unwrap().to_string(),
+            ar_path: which("llvm-ar")?.to_str().unwrap().to_string(),
+        });
+    }
+}
+
+impl CompilerBackend for ClangCompilerBackend {
+    fn identity(&self) -> &str {
+        return &self.identity;
+    }
+
+    fn compile_object(
+        &self,
+        source_path: &str,
+        output_path: &str,
+        include_directories: &[String],
+        defines: &[String],
+        extra_options: &ExtraCompileOptions,
+        gen_compile_commands: bool,
+    ) -> std::io::Result<Option<Vec<String>>> {
+        if Language::of_source(source_path) == Some(Language::Nasm) {
+            return assemble_nasm(
+                self.assembler.as_ref(),
+                source_path,
+                output_path,
+                include_directories,
+                defines,
+                extra_options,
+                gen_compile_commands,
+            );
+        }
+
+        let args = compile_args(
+            CompilerKind::Clang,
+            source_path,
+            output_path,
+            include_directories,
+            defines,
+            extra_options,
+        );
 
         if gen_compile_commands {
             let mut out_args = vec![self.compiler_path.clone()];
@@ -153,27 +926,33 @@ impl ClangCompilerBackend {
             return Ok(Some(out_args));
         }
 
-        let status = Command::new(&self.compiler_path).args(&args).status()?;
-
-        if !status.success() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Clang exited with status {}", status),
-            ));
-        }
+        run_compiler(
+            "Clang",
+            &self.compiler_path,
+            &args,
+            "-fcolor-diagnostics",
+            source_path,
+            output_path,
+        )?;
 
         return Ok(None);
     }
 
-    pub fn archive_objects(
+    fn archive_command(
         &self,
         object_paths: &[String],
         output_path: &str,
         extra_options: &ExtraCompileOptions,
-    ) -> std::io::Result<()> {
-        let mut args: Vec<String> = vec![];
+    ) -> Vec<String> {
+        let archiver = if extra_options.target.is_msvc() {
+            &self.lib_path
+        } else {
+            &self.ar_path
+        };
+
+        let mut args: Vec<String> = vec![archiver.clone()];
 
-        if target_is_msvc(&extra_options.target) {
+        if extra_options.target.is_msvc() {
             args.push(format!("/OUT:{}", output_path));
         } else {
             args.extend(["rcs".into(), output_path.into()]);
@@ -181,25 +960,200 @@ impl ClangCompilerBackend {
 
         args.extend(object_paths.iter().cloned());
 
-        let archiver = if target_is_msvc(&extra_options.target) {
-            &self.lib_path
+        return args;
+    }
+
+    fn link_command(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        lib_paths: &[String],
+        dynamic_libs: &[String],
+        static_libs: &[String],
+        dynamic_library: bool,
+        cpp: bool,
+        extra_options: &ExtraCompileOptions,
+    ) -> Vec<String> {
+        let driver = if cpp {
+            &self.cxx_compiler_path
         } else {
-            &self.ar_path
+            &self.compiler_path
         };
 
-        let status = Command::new(archiver).args(&args).status()?;
+        return link_args(
+            CompilerKind::Clang,
+            driver,
+            object_paths,
+            output_path,
+            lib_paths,
+            dynamic_libs,
+            static_libs,
+            dynamic_library,
+            cpp,
+            extra_options,
+        );
+    }
+}
 
-        if !status.success() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Archiver exited with status {}", status),
-            ));
+/// GCC, with binutils' `ar`. Builds for anything but the host go through a cross
+/// compiler named after the target, like `aarch64-linux-gnu-gcc`.
+pub struct GccCompilerBackend {
+    compiler_path: String,
+    /// `g++`, used for linking anything with C++ in it.
+    cxx_compiler_path: String,
+    ar_path: String,
+    assembler: Option<Assembler>,
+    identity: String,
+}
+
+impl GccCompilerBackend {
+    pub fn new(target: &Target) -> std::io::Result<Self> {
+        if target.is_msvc() {
+            return Err(std::io::Error::other(format!(
+                "GCC can't build for {target}, use Clang instead"
+            )));
         }
 
-        return Ok(());
+        let mut prefixes = vec![];
+
+        if *target == Target::host() {
+            prefixes.push(String::new());
+        }
+
+        // Distributions tend to leave the vendor out of the name.
+        prefixes.push(format!("{target}-"));
+
+        let mut short_name = format!("{}-{}", target.arch, target.os);
+
+        if let Some(env) = &target.env {
+            short_name = format!("{short_name}-{env}");
+        }
+
+        prefixes.push(format!("{short_name}-"));
+
+        let Some((prefix, compiler_path)) = prefixes.iter().find_map(|prefix| {
+            return which(format!("{prefix}gcc")).ok().map(|it| (prefix, it));
+        }) else {
+            return Err(std::io::Error::other(format!(
+                "Can't find GCC for {target}, tried {}",
+                prefixes
+                    .iter()
+                    .map(|it| format!("{it}gcc"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        };
+
+        let find = |name: &str| {
+            let name = format!("{prefix}{name}");
+
+            return which(&name)
+                .map_err(|_| std::io::Error::other(format!("Can't find {name}, which GCC needs")));
+        };
+
+        let cxx_compiler_path = find("g++")?;
+
+        let ar_path = find("ar")?;
+
+        let assembler = Assembler::find();
+
+        let mut identity = format!(
+            "{}\n{}",
+            tool_identity(&compiler_path),
+            tool_identity(&cxx_compiler_path)
+        );
+
+        if let Some(assembler) = &assembler {
+            identity.push('\n');
+            identity.push_str(&tool_identity(Path::new(&assembler.path)));
+        }
+
+        return Ok(Self {
+            identity,
+            assembler,
+            compiler_path: compiler_path.to_str().unwrap().to_string(),
+            cxx_compiler_path: cxx_compiler_path.to_str().unwrap().to_string(),
+            ar_path: ar_path.to_str().unwrap().to_string(),
+        });
+    }
+}
+
+impl CompilerBackend for GccCompilerBackend {
+    fn identity(&self) -> &str {
+        return &self.identity;
     }
 
-    pub fn link_objects(
+    fn compile_object(
+        &self,
+        source_path: &str,
+        output_path: &str,
+        include_directories: &[String],
+        defines: &[String],
+        extra_options: &ExtraCompileOptions,
+        gen_compile_commands: bool,
+    ) -> std::io::Result<Option<Vec<String>>> {
+        if Language::of_source(source_path) == Some(Language::Nasm) {
+            return assemble_nasm(
+                self.assembler.as_ref(),
+                source_path,
+                output_path,
+                include_directories,
+                defines,
+                extra_options,
+                gen_compile_commands,
+            );
+        }
+
+        if Language::of_source(source_path) == Some(Language::Cpp)
+            && extra_options.cpp_stdlib == Some(CppStdlib::LibCpp)
+        {
+            return Err(std::io::Error::other(format!(
+                "Can't compile {source_path} with libc++, GCC only supports libstdc++. Set build.cpp_stdlib to \"libstdc++\" or build with Clang"
+            )));
+        }
+
+        let args = compile_args(
+            CompilerKind::Gcc,
+            source_path,
+            output_path,
+            include_directories,
+            defines,
+            extra_options,
+        );
+
+        if gen_compile_commands {
+            let mut out_args = vec![self.compiler_path.clone()];
+            out_args.extend(args);
+
+            return Ok(Some(out_args));
+        }
+
+        run_compiler(
+            "GCC",
+            &self.compiler_path,
+            &args,
+            "-fdiagnostics-color=always",
+            source_path,
+            output_path,
+        )?;
+
+        return Ok(None);
+    }
+
+    fn archive_command(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        _extra_options: &ExtraCompileOptions,
+    ) -> Vec<String> {
+        let mut args: Vec<String> = vec![self.ar_path.clone(), "rcs".into(), output_path.into()];
+
+        args.extend(object_paths.iter().cloned());
+
+        return args;
+    }
+
+    fn link_command(
         &self,
         object_paths: &[String],
         output_path: &str,
@@ -207,46 +1161,103 @@ impl ClangCompilerBackend {
         dynamic_libs: &[String],
         static_libs: &[String],
         dynamic_library: bool,
+        cpp: bool,
         extra_options: &ExtraCompileOptions,
-    ) -> std::io::Result<()> {
-        let mut args: Vec<String> = vec![];
+    ) -> Vec<String> {
+        let driver = if cpp {
+            &self.cxx_compiler_path
+        } else {
+            &self.compiler_path
+        };
 
-        args.extend(object_paths.iter().cloned());
+        return link_args(
+            CompilerKind::Gcc,
+            driver,
+            object_paths,
+            output_path,
+            lib_paths,
+            dynamic_libs,
+            static_libs,
+            dynamic_library,
+            cpp,
+            extra_options,
+        );
+    }
+}
 
-        args.extend(lib_paths.iter().map(|it| format!("-L{}", it)));
-        args.extend(dynamic_libs.iter().map(|it| format!("-l{}", it)));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if !target_is_msvc(&extra_options.target) {
-            args.push(format!("-Wl,--whole-archive"));
-        }
+    fn standard_flags(source: &str, options: &ExtraCompileOptions) -> Vec<String> {
+        return compile_args(CompilerKind::Gcc, source, "out.o", &[], &[], options)
+            .into_iter()
+            .filter(|it| it.starts_with("-std="))
+            .collect();
+    }
 
-        args.extend(static_libs.iter().map(|it| format!("-l{}", it)));
+    #[test]
+    fn gnu_extensions_pick_a_standard() {
+        let mut options = ExtraCompileOptions::default();
 
-        if !target_is_msvc(&extra_options.target) {
-            args.push("-Wl,--no-whole-archive".into());
-        }
+        assert_eq!(standard_flags("main.c", &options), Vec::<String>::new());
 
-        if dynamic_library {
-            args.push("-shared".into());
-        }
+        options.gnu_extensions = Some(false);
 
-        args.extend(["-o".into(), output_path.into()]);
+        assert_eq!(standard_flags("main.c", &options), ["-std=c17"]);
+        assert_eq!(standard_flags("main.cpp", &options), ["-std=c++17"]);
+        assert_eq!(standard_flags("start.s", &options), Vec::<String>::new());
 
-        args.extend(["-target".into(), extra_options.target.clone()]);
+        options.gnu_extensions = Some(true);
+        options.cpp_standard = LanguageStandard::parse("c++20");
 
-        if extra_options.lto {
-            args.push("-flto".into());
-        }
+        assert_eq!(standard_flags("main.c", &options), ["-std=gnu17"]);
+        assert_eq!(standard_flags("main.cpp", &options), ["-std=gnu++20"]);
+    }
 
-        let status = Command::new(&self.compiler_path).args(&args).status()?;
+    fn static_lib_flags(target: &str) -> Vec<String> {
+        let options = ExtraCompileOptions {
+            target: Target::parse(target).unwrap(),
+            ..Default::default()
+        };
 
-        if !status.success() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Linker exited with status {}", status),
-            ));
-        }
+        let static_libs = ["a/libone.a".to_string(), "b/libtwo.a".to_string()];
 
-        return Ok(());
+        return link_args(
+            CompilerKind::Clang,
+            "clang",
+            &["main.o".into()],
+            "app",
+            &[],
+            &[],
+            &static_libs,
+            false,
+            false,
+            &options,
+        )
+        .into_iter()
+        .filter(|it| it.contains("archive") || it.contains("load") || it.ends_with(".a"))
+        .collect();
+    }
+
+    #[test]
+    fn static_libraries_are_linked_whole() {
+        assert_eq!(
+            static_lib_flags("x86_64-pc-linux-gnu"),
+            [
+                "-Wl,--whole-archive",
+                "a/libone.a",
+                "b/libtwo.a",
+                "-Wl,--no-whole-archive"
+            ]
+        );
+
+        // ld64 doesn't know --whole-archive.
+        for target in ["aarch64-apple-darwin", "arm64-apple-ios"] {
+            assert_eq!(
+                static_lib_flags(target),
+                ["-Wl,-force_load,a/libone.a", "-Wl,-force_load,b/libtwo.a"]
+            );
+        }
     }
 }
@@ -0,0 +1,253 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::{
+    interner::StrReference,
+    project::{Workspace, WorkspaceError},
+};
+
+/// The features asked for on the command line. They apply to the packages being built.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureSelection {
+    /// Either `<feature>` or `<package>/<feature>`.
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+}
+
+/// Turns a feature name into the define it's passed to the compiler as.
+pub fn feature_define(feature: &str) -> String {
+    let name: String = feature
+        .chars()
+        .map(|it| {
+            if it.is_ascii_alphanumeric() {
+                it.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    return format!("DOGGO_FEATURE_{name}");
+}
+
+enum Request {
+    /// The package gets built, so everything it always depends on does too.
+    Activate,
+    Feature(String),
+    Dependency(StrReference),
+}
+
+#[derive(Default)]
+struct Resolved {
+    active: HashSet<usize>,
+    features: HashMap<usize, BTreeSet<String>>,
+    dependencies: HashMap<usize, HashSet<StrReference>>,
+}
+
+impl Workspace {
+    /// Works out which features and optional dependencies are enabled when building `roots`.
+    /// Every package gets the union of what anything depending on it asked for, so it's
+    /// only ever built once.
+    pub fn resolve_features(
+        &mut self,
+        roots: &[usize],
+        selection: &FeatureSelection,
+    ) -> Result<(), WorkspaceError> {
+        let mut queue = VecDeque::new();
+
+        for root in roots {
+            queue.push_back((*root, Request::Activate));
+
+            let package = self.package(*root);
+
+            if selection.all_features {
+                for feature in package.features.keys() {
+                    queue.push_back((*root, Request::Feature(feature.clone())));
+                }
+
+                for (name, dependency) in &package.dependency_manifests {
+                    if dependency.optional {
+                        queue.push_back((*root, Request::Dependency(name.clone())));
+                    }
+                }
+            } else if !selection.no_default_features && package.features.contains_key("default") {
+                queue.push_back((*root, Request::Feature("default".into())));
+            }
+        }
+
+        for feature in &selection.features {
+            match feature.split_once('/') {
+                Some((package, feature)) => {
+                    let index = self.member_index(package)?;
+
+                    queue.push_back((index, Request::Activate));
+                    queue.push_back((index, Request::Feature(feature.to_string())));
+                }
+                None => {
+                    for root in roots {
+                        queue.push_back((*root, Request::Feature(feature.clone())));
+                    }
+                }
+            }
+        }
+
+        let mut resolved = Resolved::default();
+
+        while let Some((index, request)) = queue.pop_front() {
+            self.resolve_request(index, request, &mut resolved, &mut queue)?;
+        }
+
+        for index in 0..self.members.len() + self.external_packages.len() {
+            let package = self.package_mut(index);
+
+            package.enabled_features = resolved.features.remove(&index).unwrap_or_default();
+            package.enabled_dependencies = resolved.dependencies.remove(&index).unwrap_or_default();
+        }
+
+        return Ok(());
+    }
+
+    fn resolve_request(
+        &self,
+        index: usize,
+        request: Request,
+        resolved: &mut Resolved,
+        queue: &mut VecDeque<(usize, Request)>,
+    ) -> Result<(), WorkspaceError> {
+        let package = self.package(index);
+
+        match request {
+            Request::Activate => {
+                if !resolved.active.insert(index) {
+                    return Ok(());
+                }
+
+                for name in &package.dependencies {
+                    let optional = package
+                        .dependency_manifests
+                        .get(name)
+                        .is_some_and(|it| it.optional);
+
+                    if !optional {
+                        self.enable_dependency(index, name, queue)?;
+                    }
+                }
+            }
+            Request::Feature(feature) => {
+                let enabled = resolved.features.entry(index).or_default();
+
+                if enabled.contains(&feature) {
+                    return Ok(());
+                }
+
+                // Optional dependencies double as features, unless a feature has the same name.
+                let Some(enables) = package.features.get(&feature) else {
+                    let dependency = package
+                        .dependency_manifests
+                        .iter()
+                        .find(|(name, it)| it.optional && name.get().as_ref() == feature);
+
+                    let Some((name, _)) = dependency else {
+                        return Err(WorkspaceError::FeatureNotFound(
+                            feature,
+                            package.name.get().to_string(),
+                        ));
+                    };
+
+                    queue.push_back((index, Request::Dependency(name.clone())));
+
+                    return Ok(());
+                };
+
+                enabled.insert(feature.clone());
+
+                for entry in enables {
+                    if let Some(name) = entry.strip_prefix("dep:") {
+                        queue.push_back((
+                            index,
+                            Request::Dependency(self.dependency_name(index, name)?),
+                        ));
+                    } else if let Some((name, dependency_feature)) = entry.split_once('/') {
+                        let name = self.dependency_name(index, name)?;
+
+                        if !package.dependencies.contains(&name) {
+                            continue;
+                        }
+
+                        queue.push_back((index, Request::Dependency(name.clone())));
+
+                        let dependency = self.dependency_index(&name)?;
+
+                        queue.push_back((
+                            dependency,
+                            Request::Feature(dependency_feature.to_string()),
+                        ));
+                    } else {
+                        queue.push_back((index, Request::Feature(entry.clone())));
+                    }
+                }
+            }
+            Request::Dependency(name) => {
+                // Dependencies of other targets can be named, but don't do anything.
+                if !package.dependencies.contains(&name) {
+                    return Ok(());
+                }
+
+                if resolved
+                    .dependencies
+                    .entry(index)
+                    .or_default()
+                    .insert(name.clone())
+                {
+                    self.enable_dependency(index, &name, queue)?;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Activates a dependency of the package at `index`, with the features it was declared with.
+    fn enable_dependency(
+        &self,
+        index: usize,
+        name: &StrReference,
+        queue: &mut VecDeque<(usize, Request)>,
+    ) -> Result<(), WorkspaceError> {
+        // Cargo resolves the features of Rust crates itself.
+        if self.package(index).cargo_crates.contains_key(name) {
+            return Ok(());
+        }
+
+        let dependency = self.dependency_index(name)?;
+
+        queue.push_back((dependency, Request::Activate));
+
+        let Some(manifest) = self.package(index).dependency_manifests.get(name) else {
+            return Ok(());
+        };
+
+        if manifest.default_features && self.package(dependency).features.contains_key("default") {
+            queue.push_back((dependency, Request::Feature("default".into())));
+        }
+
+        for feature in &manifest.features {
+            queue.push_back((dependency, Request::Feature(feature.clone())));
+        }
+
+        return Ok(());
+    }
+
+    /// The package's own reference to the dependency called `name`.
+    fn dependency_name(&self, index: usize, name: &str) -> Result<StrReference, WorkspaceError> {
+        return self
+            .package(index)
+            .dependency_manifests
+            .keys()
+            .find(|it| it.get().as_ref() == name)
+            .cloned()
+            .ok_or_else(|| {
+                WorkspaceError::DependencyNotInWorkspace(name.to_string(), self.path.clone())
+            });
+    }
+}
@@ -0,0 +1,43 @@
+use std::{fs, io, path::PathBuf};
+
+/// The command used to produce an output, plus the identity of the tool that ran it.
+/// It's stored next to the output, so a changed flag, define or compiler forces a
+/// rebuild, even when none of the inputs changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    text: String,
+}
+
+impl Fingerprint {
+    pub fn new(identity: &str, command: &[String]) -> Self {
+        let mut text = identity.to_string();
+
+        for arg in command {
+            text.push('\n');
+            text.push_str(arg);
+        }
+
+        text.push('\n');
+
+        return Self { text };
+    }
+
+    pub fn path_for(output_path: &str) -> PathBuf {
+        return PathBuf::from(format!("{output_path}.cmd"));
+    }
+
+    /// Whether the output at `output_path` was produced by this exact command.
+    pub fn matches(&self, output_path: &str) -> io::Result<bool> {
+        let path = Self::path_for(output_path);
+
+        if !fs::exists(&path)? {
+            return Ok(false);
+        }
+
+        return Ok(fs::read_to_string(path)? == self.text);
+    }
+
+    pub fn write(&self, output_path: &str) -> io::Result<()> {
+        return fs::write(Self::path_for(output_path), &self.text);
+    }
+}
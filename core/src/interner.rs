@@ -20,7 +20,6 @@ struct StringPoolValue {
     ref_count: usize,
 }
 
-#[derive(PartialOrd)]
 pub struct StrReference {
     index: usize,
 }
@@ -168,6 +167,23 @@ impl PartialEq for StrReference {
 
 impl Eq for StrReference {}
 
+/// Ordered by the strings themselves, so maps keyed by them iterate the same way every run.
+impl PartialOrd for StrReference {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for StrReference {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        if self.index == other.index {
+            return std::cmp::Ordering::Equal;
+        }
+
+        return self.get().cmp(&other.get());
+    }
+}
+
 impl Hash for StrReference {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.index.hash(state);
@@ -1,21 +1,28 @@
-#![feature(decl_macro, str_as_str, fn_traits)]
+#![allow(clippy::needless_return)]
 
 use std::{fs, io, path::Path, time::SystemTime};
 
+pub mod bindings;
+pub mod build;
+pub mod cargo;
 pub mod compiler_backend;
+pub mod features;
+pub mod fingerprint;
 pub mod interner;
 pub mod manifest;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod plan;
+pub mod profile;
 pub mod project;
+pub mod scheduler;
+pub mod target;
 
-pub const BUILD_DIR: &'static str = ".doggo";
+pub const BUILD_DIR: &str = ".doggo";
 
-pub fn target_is_msvc(target: &str) -> bool {
-    return target.ends_with("msvc");
-}
-
-pub fn target_is_windows(target: &str) -> bool {
-    return target.contains("windows");
-}
+/// These sit next to package outputs in the build directory, so no package can be called that.
+pub const OBJECT_DIR: &str = "obj";
+pub const CARGO_DIR: &str = "cargo";
 
 pub const fn get_default_target() -> &'static str {
     #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
@@ -40,13 +47,22 @@ pub const fn get_default_target() -> &'static str {
     return "unknown-unknown-unknown";
 }
 
-pub const DEFAULT_TARGET: &'static str = get_default_target();
+pub const DEFAULT_TARGET: &str = get_default_target();
 
 pub fn walk_dir<F: FnMut(&str) -> io::Result<()>>(path: &Path, consumer: &mut F) -> io::Result<()> {
-    let read = path.read_dir()?;
-
     let base_path = path.to_str().unwrap().to_string();
 
+    return walk_dir_from(&base_path, path, consumer);
+}
+
+/// Paths passed to the consumer are relative to `base_path`, even in subdirectories.
+fn walk_dir_from<F: FnMut(&str) -> io::Result<()>>(
+    base_path: &str,
+    path: &Path,
+    consumer: &mut F,
+) -> io::Result<()> {
+    let read = path.read_dir()?;
+
     for entry in read {
         let entry = entry?;
 
@@ -54,16 +70,18 @@ pub fn walk_dir<F: FnMut(&str) -> io::Result<()>>(path: &Path, consumer: &mut F)
 
         let entry_path = entry.path();
         if entry_path.is_dir() {
-            walk_dir(&entry_path, consumer)?;
+            walk_dir_from(base_path, &entry_path, consumer)?;
         } else if entry_path.is_file() {
-            consumer.call_mut((&path[base_path.len() + 1..],))?;
+            consumer(&path[base_path.len() + 1..])?;
         }
     }
 
     return Ok(());
 }
 
-fn read_depfile(dependency_path: &str, file_path: &str) -> std::io::Result<Vec<String>> {
+/// Returns `None` if the depfile doesn't have a rule for `file_path`, like when it was
+/// written for an output with another name.
+fn read_depfile(dependency_path: &str, file_path: &str) -> std::io::Result<Option<Vec<String>>> {
     let text = fs::read_to_string(dependency_path)?;
 
     let depfile = depfile::parse(&text)
@@ -71,10 +89,27 @@ fn read_depfile(dependency_path: &str, file_path: &str) -> std::io::Result<Vec<S
 
     return Ok(depfile
         .find(file_path)
-        .unwrap()
-        .iter()
-        .map(|it| it.to_string())
-        .collect());
+        .map(|it| it.iter().map(|it| it.to_string()).collect()));
+}
+
+/// Every prerequisite of every rule in the depfile.
+pub fn read_depfile_inputs(dependency_path: &str) -> io::Result<Vec<String>> {
+    let text = fs::read_to_string(dependency_path)?;
+
+    let depfile = depfile::parse(&text)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}", e)))?;
+
+    let mut inputs: Vec<String> = vec![];
+
+    for (_, dependencies) in depfile.iter() {
+        for dependency in dependencies {
+            if !inputs.iter().any(|it| it == dependency) {
+                inputs.push(dependency.to_string());
+            }
+        }
+    }
+
+    return Ok(inputs);
 }
 
 fn file_creation_time(path: &str) -> std::io::Result<SystemTime> {
@@ -88,7 +123,9 @@ pub fn file_up_to_date(dependency_path: &str, file_path: &str) -> io::Result<boo
         return Ok(false);
     }
 
-    let dependencies = read_depfile(dependency_path, file_path)?;
+    let Some(dependencies) = read_depfile(dependency_path, file_path)? else {
+        return Ok(false);
+    };
 
     let base_time = file_creation_time(dependency_path)?;
 
@@ -106,3 +143,20 @@ pub fn file_up_to_date(dependency_path: &str, file_path: &str) -> io::Result<boo
 
     return Ok(true);
 }
+
+/// Whether `output_path` exists and is newer than every one of `inputs`.
+pub fn output_up_to_date(output_path: &str, inputs: &[String]) -> io::Result<bool> {
+    if !fs::exists(output_path)? {
+        return Ok(false);
+    }
+
+    let base_time = file_creation_time(output_path)?;
+
+    for input in inputs {
+        if !fs::exists(input)? || file_creation_time(input)? > base_time {
+            return Ok(false);
+        }
+    }
+
+    return Ok(true);
+}
@@ -1,17 +1,42 @@
 use serde::Deserialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
     fs, io,
     path::{Path, PathBuf},
 };
 
-use crate::interner::StrReference;
+use crate::{
+    CARGO_DIR, OBJECT_DIR,
+    cargo::HeaderGenerator,
+    compiler_backend::{CompilerKind, CppRuntime, CppStdlib, Language, LanguageStandard, OptLevel},
+    interner::StrReference,
+    target::Target,
+};
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
 pub struct Dependency {
     pub path: Option<PathBuf>,
     #[serde(default)]
     pub workspace: bool,
+    /// A Rust crate building a `staticlib` or `cdylib`, built with Cargo.
+    pub cargo: Option<PathBuf>,
+    /// Generates a C header for a `cargo` dependency.
+    pub header: Option<HeaderGenerator>,
+    /// Only built when one of our features turns it on.
+    #[serde(default)]
+    pub optional: bool,
+    /// Features to enable on the dependency.
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default = "Dependency::default_features", alias = "default-features")]
+    pub default_features: bool,
+}
+
+impl Dependency {
+    fn default_features() -> bool {
+        return true;
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Default)]
@@ -38,12 +63,482 @@ pub struct PackageManifest {
     pub output: PackageKind,
     #[serde(default)]
     pub lto: bool,
+    /// Header directories dependents get on their include path, relative to the package.
+    #[serde(
+        default = "PackageManifest::default_public_include",
+        alias = "public-include"
+    )]
+    pub public_include: Vec<PathBuf>,
+    /// Header directories only this package gets on its include path.
+    #[serde(
+        default = "PackageManifest::default_private_include",
+        alias = "private-include"
+    )]
+    pub private_include: Vec<PathBuf>,
+    /// Like `c11` or `gnu17`. Takes priority over the profile's.
+    #[serde(alias = "c-standard")]
+    pub c_standard: Option<LanguageStandard>,
+    /// Like `c++20` or `gnu++17`. Takes priority over the profile's.
+    #[serde(alias = "cpp-standard")]
+    pub cpp_standard: Option<LanguageStandard>,
+    /// Turns the `gnu` variant of both standards on or off. Without a standard,
+    /// C17 and C++17 are used, like compilers default to.
+    #[serde(alias = "gnu-extensions")]
+    pub gnu_extensions: Option<bool>,
+}
+
+impl PackageManifest {
+    fn default_public_include() -> Vec<PathBuf> {
+        return vec!["include".into()];
+    }
+
+    fn default_private_include() -> Vec<PathBuf> {
+        return vec!["src".into()];
+    }
+}
+
+/// The `[build]` table. Everything here only applies to the package itself.
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BuildManifest {
+    /// Either `NAME` or `NAME=VALUE`.
+    #[serde(default)]
+    pub defines: Vec<String>,
+    /// Extra include directories, relative to the package.
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+    /// Flags passed when compiling C sources.
+    #[serde(default)]
+    pub cflags: Vec<String>,
+    /// Flags passed when compiling C++ sources.
+    #[serde(default)]
+    pub cxxflags: Vec<String>,
+    /// Flags passed when assembling `.s` and `.S` sources.
+    #[serde(default)]
+    pub asflags: Vec<String>,
+    /// Flags passed to NASM or YASM for `.asm` sources.
+    #[serde(default)]
+    pub nasmflags: Vec<String>,
+    /// Flags passed when linking.
+    #[serde(default)]
+    pub ldflags: Vec<String>,
+    /// System libraries to link against, without the `-l`.
+    #[serde(default)]
+    pub link: Vec<String>,
+    /// Target triple to build for when `--target` isn't given. Only read from the root manifest.
+    pub target: Option<Target>,
+    /// `libc++` or `libstdc++`. Only read from the root manifest, since everything linked
+    /// together needs to agree on it.
+    #[serde(alias = "cpp-stdlib")]
+    pub cpp_stdlib: Option<CppStdlib>,
+    /// `shared` or `static`. Only read from the root manifest.
+    #[serde(alias = "cpp-runtime")]
+    pub cpp_runtime: Option<CppRuntime>,
+    /// `clang` or `gcc`, when `--compiler` isn't given. Only read from the root manifest.
+    pub compiler: Option<CompilerKind>,
+}
+
+fn validate_defines(table: &str, defines: &[String]) -> Result<(), String> {
+    for define in defines {
+        let name = define.split_once('=').map_or(define.as_str(), |it| it.0);
+
+        let valid = name.starts_with(|it: char| it.is_ascii_alphabetic() || it == '_')
+            && name
+                .chars()
+                .all(|it| it.is_ascii_alphanumeric() || it == '_');
+
+        if !valid {
+            return Err(format!("{table}.defines: {define:?} is not a valid define"));
+        }
+    }
+
+    return Ok(());
+}
+
+impl BuildManifest {
+    /// Adds everything from a matching `[target.<...>.build]` table.
+    pub fn merge(&mut self, other: &BuildManifest) {
+        self.defines.extend(other.defines.iter().cloned());
+        self.include.extend(other.include.iter().cloned());
+        self.cflags.extend(other.cflags.iter().cloned());
+        self.cxxflags.extend(other.cxxflags.iter().cloned());
+        self.asflags.extend(other.asflags.iter().cloned());
+        self.nasmflags.extend(other.nasmflags.iter().cloned());
+        self.ldflags.extend(other.ldflags.iter().cloned());
+
+        for library in &other.link {
+            if !self.link.contains(library) {
+                self.link.push(library.clone());
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        validate_defines("build", &self.defines)?;
+
+        for (table, flags) in [
+            ("cflags", &self.cflags),
+            ("cxxflags", &self.cxxflags),
+            ("asflags", &self.asflags),
+            ("nasmflags", &self.nasmflags),
+            ("ldflags", &self.ldflags),
+        ] {
+            for flag in flags {
+                if flag.trim().is_empty() {
+                    return Err(format!("build.{table}: flags can't be empty"));
+                }
+            }
+        }
+
+        for library in &self.link {
+            if library.starts_with("-l") {
+                return Err(format!(
+                    "build.link: {library:?} should be given without the -l"
+                ));
+            }
+
+            if library.is_empty() || library.contains(char::is_whitespace) {
+                return Err(format!(
+                    "build.link: {library:?} is not a valid library name"
+                ));
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// A `[profile.<name>]` table. Anything left out comes from the profile it inherits from.
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileManifest {
+    pub inherits: Option<String>,
+    #[serde(alias = "opt-level")]
+    pub opt_level: Option<OptLevel>,
+    pub debug: Option<bool>,
+    pub lto: Option<bool>,
+    /// When false, `NDEBUG` gets defined.
+    pub assertions: Option<bool>,
+    pub defines: Option<Vec<String>>,
+    /// Used by packages that don't pick their own.
+    #[serde(alias = "c-standard")]
+    pub c_standard: Option<LanguageStandard>,
+    /// Used by packages that don't pick their own.
+    #[serde(alias = "cpp-standard")]
+    pub cpp_standard: Option<LanguageStandard>,
+    #[serde(alias = "gnu-extensions")]
+    pub gnu_extensions: Option<bool>,
+}
+
+/// Names end up as file names and `-l` flags, so only plain ones are allowed.
+pub fn validate_package_name(name: &str) -> Result<(), String> {
+    let valid = name
+        .chars()
+        .all(|it| it.is_ascii_alphanumeric() || it == '-' || it == '_');
+
+    if name.is_empty() || !valid {
+        return Err(format!(
+            "package.name: {name:?} can only have letters, digits, - and _"
+        ));
+    }
+
+    if [OBJECT_DIR, CARGO_DIR].contains(&name) {
+        return Err(format!(
+            "package.name: {name:?} is reserved for the build directory"
+        ));
+    }
+
+    return Ok(());
+}
+
+fn validate_standards(
+    table: &str,
+    c_standard: Option<LanguageStandard>,
+    cpp_standard: Option<LanguageStandard>,
+) -> Result<(), String> {
+    if c_standard.is_some_and(|it| it.language != Language::C) {
+        return Err(format!(
+            "{table}.c_standard: expected a C standard, like \"c11\""
+        ));
+    }
+
+    if cpp_standard.is_some_and(|it| it.language != Language::Cpp) {
+        return Err(format!(
+            "{table}.cpp_standard: expected a C++ standard, like \"c++20\""
+        ));
+    }
+
+    return Ok(());
+}
+
+/// A parsed `cfg(...)` expression, like Cargo's.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CfgExpr {
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+    /// A bare name, like `unix`.
+    Name(String),
+    /// `key = "value"`, like `target_os = "linux"`.
+    KeyValue(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CfgToken {
+    Ident(String),
+    Str(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    Equals,
+}
+
+const CFG_NAMES: &[&str] = &["unix", "windows"];
+
+const CFG_KEYS: &[&str] = &[
+    "target_arch",
+    "target_os",
+    "target_vendor",
+    "target_env",
+    "target_family",
+    "target_pointer_width",
+];
+
+fn tokenize_cfg(text: &str) -> Result<Vec<CfgToken>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '(' => tokens.push(CfgToken::LeftParen),
+            ')' => tokens.push(CfgToken::RightParen),
+            ',' => tokens.push(CfgToken::Comma),
+            '=' => tokens.push(CfgToken::Equals),
+            '"' => {
+                let mut value = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(char) => value.push(char),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+
+                tokens.push(CfgToken::Str(value));
+            }
+            _ if char.is_whitespace() => {}
+            _ if char.is_ascii_alphanumeric() || char == '_' => {
+                let mut ident = char.to_string();
+
+                while let Some(next) = chars.next_if(|it| it.is_ascii_alphanumeric() || *it == '_')
+                {
+                    ident.push(next);
+                }
+
+                tokens.push(CfgToken::Ident(ident));
+            }
+            _ => return Err(format!("unexpected character {char:?}")),
+        }
+    }
+
+    return Ok(tokens);
+}
+
+impl CfgExpr {
+    /// Parses the inside of `cfg(...)`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize_cfg(text)?;
+        let mut position = 0;
+
+        let expr = Self::parse_expr(&tokens, &mut position)?;
+
+        if position != tokens.len() {
+            return Err("unexpected tokens after the expression".into());
+        }
+
+        return Ok(expr);
+    }
+
+    fn parse_expr(tokens: &[CfgToken], position: &mut usize) -> Result<Self, String> {
+        let Some(CfgToken::Ident(ident)) = tokens.get(*position) else {
+            return Err("expected a name".into());
+        };
+
+        *position += 1;
+
+        match tokens.get(*position) {
+            Some(CfgToken::Equals) => {
+                *position += 1;
+
+                let Some(CfgToken::Str(value)) = tokens.get(*position) else {
+                    return Err(format!("expected a string after {ident} ="));
+                };
+
+                *position += 1;
+
+                if !CFG_KEYS.contains(&ident.as_str()) {
+                    return Err(format!("unknown key {ident:?}"));
+                }
+
+                return Ok(Self::KeyValue(ident.clone(), value.clone()));
+            }
+            Some(CfgToken::LeftParen) => {
+                *position += 1;
+
+                let mut children = vec![];
+
+                while tokens.get(*position) != Some(&CfgToken::RightParen) {
+                    children.push(Self::parse_expr(tokens, position)?);
+
+                    match tokens.get(*position) {
+                        Some(CfgToken::Comma) => *position += 1,
+                        Some(CfgToken::RightParen) => {}
+                        _ => return Err(format!("expected , or ) in {ident}(...)")),
+                    }
+                }
+
+                *position += 1;
+
+                return match ident.as_str() {
+                    "all" => Ok(Self::All(children)),
+                    "any" => Ok(Self::Any(children)),
+                    "not" if children.len() == 1 => Ok(Self::Not(Box::new(children.remove(0)))),
+                    "not" => Err("not(...) takes exactly one expression".into()),
+                    _ => Err(format!("unknown operator {ident:?}")),
+                };
+            }
+            _ => {
+                if !CFG_NAMES.contains(&ident.as_str()) {
+                    return Err(format!("unknown name {ident:?}"));
+                }
+
+                return Ok(Self::Name(ident.clone()));
+            }
+        }
+    }
+
+    pub fn matches(&self, target: &Target) -> bool {
+        return match self {
+            Self::All(children) => children.iter().all(|it| it.matches(target)),
+            Self::Any(children) => children.iter().any(|it| it.matches(target)),
+            Self::Not(child) => !child.matches(target),
+            Self::Name(name) => target.family() == Some(name.as_str()),
+            Self::KeyValue(key, value) => match key.as_str() {
+                "target_arch" => target.cfg_arch() == value || &target.arch == value,
+                "target_os" => target.cfg_os() == value || &target.os == value,
+                "target_vendor" => &target.vendor == value,
+                "target_env" => target.env.as_deref().unwrap_or_default() == value,
+                "target_family" => target.family() == Some(value.as_str()),
+                "target_pointer_width" => target.pointer_width().to_string() == *value,
+                _ => false,
+            },
+        };
+    }
+}
+
+impl Display for CfgExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |children: &[CfgExpr]| {
+            return children
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+        };
+
+        return match self {
+            Self::All(children) => write!(f, "all({})", list(children)),
+            Self::Any(children) => write!(f, "any({})", list(children)),
+            Self::Not(child) => write!(f, "not({child})"),
+            Self::Name(name) => write!(f, "{name}"),
+            Self::KeyValue(key, value) => write!(f, "{key} = {value:?}"),
+        };
+    }
+}
+
+/// The key of a `[target.<...>]` table, either a triple or a `cfg(...)` expression.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TargetSelector {
+    Triple(Target),
+    Cfg(CfgExpr),
+}
+
+impl TargetSelector {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if let Some(expr) = text
+            .strip_prefix("cfg(")
+            .and_then(|it| it.strip_suffix(')'))
+        {
+            return Ok(Self::Cfg(
+                CfgExpr::parse(expr).map_err(|e| format!("invalid {text:?}: {e}"))?,
+            ));
+        }
+
+        return Ok(Self::Triple(
+            Target::parse(text).map_err(|e| e.to_string())?,
+        ));
+    }
+
+    /// Triples match like `cfg(...)` does, so the vendor doesn't matter and
+    /// `x86_64-linux-gnu` matches `x86_64-pc-linux-gnu`.
+    pub fn matches(&self, target: &Target) -> bool {
+        return match self {
+            Self::Triple(triple) => {
+                let arch = target.cfg_arch() == triple.arch
+                    || triple.cfg_arch() == target.arch
+                    || target.arch == triple.arch;
+
+                arch && target.cfg_os() == triple.cfg_os() && target.env == triple.env
+            }
+            Self::Cfg(expr) => expr.matches(target),
+        };
+    }
+}
+
+impl Display for TargetSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Self::Triple(triple) => write!(f, "{triple}"),
+            Self::Cfg(expr) => write!(f, "cfg({expr})"),
+        };
+    }
+}
+
+impl<'de> Deserialize<'de> for TargetSelector {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+
+        return Self::parse(&text).map_err(serde::de::Error::custom);
+    }
+}
+
+/// A `[target.<triple>]` or `[target.'cfg(...)']` table, applied when building for a matching target.
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TargetManifest {
+    /// Passed to clang as `--sysroot`, relative to the root manifest. Only read from the root manifest.
+    pub sysroot: Option<PathBuf>,
+    /// Passed to clang as `-fuse-ld`, so either a name like `lld` or a path. Only read from the root manifest.
+    pub linker: Option<String>,
+    /// Added on top of the package's `[build]` table.
+    #[serde(default)]
+    pub build: BuildManifest,
+    /// Added on top of the package's `[dependencies]`.
+    #[serde(default)]
+    pub dependencies: BTreeMap<StrReference, Dependency>,
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
 pub struct WorkspaceManifest {
     #[serde(default)]
     pub members: Vec<StrReference>,
+    /// Members built when running in the workspace root without selecting any.
+    #[serde(default, alias = "default-members")]
+    pub default_members: Vec<StrReference>,
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
@@ -58,13 +553,28 @@ pub struct Manifest {
     #[serde(flatten)]
     pub kind: ManifestKind,
     #[serde(default)]
-    pub dependencies: HashMap<StrReference, Dependency>,
+    pub dependencies: BTreeMap<StrReference, Dependency>,
+    #[serde(default)]
+    pub build: BuildManifest,
+    /// Only read from the root manifest of a workspace.
+    #[serde(default)]
+    pub profile: HashMap<String, ProfileManifest>,
+    #[serde(default)]
+    pub target: HashMap<TargetSelector, TargetManifest>,
+    /// Feature names to what they enable: other features, `dep:<name>` for optional
+    /// dependencies, or `<dependency>/<feature>`. `default` is enabled unless asked not to.
+    #[serde(default)]
+    pub features: HashMap<String, Vec<String>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum ManifestError {
+    #[error("Failed to parse TOML manifest: {0}")]
     Toml(toml::de::Error),
+    #[error("IO error: {0}")]
     Io(io::Error),
+    #[error("Invalid manifest ({0}): {1}")]
+    Invalid(PathBuf, String),
 }
 
 impl From<toml::de::Error> for ManifestError {
@@ -80,9 +590,9 @@ impl From<io::Error> for ManifestError {
 }
 
 impl Manifest {
-    const TOML_NAME: &'static str = "Doggo.toml";
+    pub const TOML_NAME: &'static str = "Doggo.toml";
 
-    pub fn load(path: &PathBuf) -> Result<Option<Manifest>, ManifestError> {
+    pub fn load(path: &Path) -> Result<Option<Manifest>, ManifestError> {
         let path = path.join(Self::TOML_NAME);
 
         let full_path = Path::new(&path);
@@ -92,6 +602,363 @@ impl Manifest {
         }
 
         let content = fs::read_to_string(full_path)?;
-        return Ok(Some(toml::from_str(&content)?));
+        let manifest: Manifest = toml::from_str(&content)?;
+
+        manifest
+            .build
+            .validate()
+            .map_err(|e| ManifestError::Invalid(path.clone(), e))?;
+
+        for (name, profile) in &manifest.profile {
+            if let Some(defines) = &profile.defines {
+                validate_defines(&format!("profile.{name}"), defines)
+                    .map_err(|e| ManifestError::Invalid(path.clone(), e))?;
+            }
+
+            validate_standards(
+                &format!("profile.{name}"),
+                profile.c_standard,
+                profile.cpp_standard,
+            )
+            .map_err(|e| ManifestError::Invalid(path.clone(), e))?;
+        }
+
+        if let ManifestKind::Package(package) = &manifest.kind {
+            validate_package_name(&package.name.get())
+                .map_err(|e| ManifestError::Invalid(path.clone(), e))?;
+
+            validate_standards("package", package.c_standard, package.cpp_standard)
+                .map_err(|e| ManifestError::Invalid(path.clone(), e))?;
+        }
+
+        for (selector, target) in &manifest.target {
+            target.build.validate().map_err(|e| {
+                ManifestError::Invalid(path.clone(), format!("target.'{selector}'.{e}"))
+            })?;
+
+            for (field, set) in [
+                ("target", target.build.target.is_some()),
+                ("cpp_stdlib", target.build.cpp_stdlib.is_some()),
+                ("cpp_runtime", target.build.cpp_runtime.is_some()),
+                ("compiler", target.build.compiler.is_some()),
+            ] {
+                if set {
+                    return Err(ManifestError::Invalid(
+                        path.clone(),
+                        format!("target.'{selector}'.build.{field}: can't be set per target"),
+                    ));
+                }
+            }
+        }
+
+        manifest
+            .validate_dependencies()
+            .map_err(|e| ManifestError::Invalid(path.clone(), e))?;
+
+        manifest
+            .validate_features()
+            .map_err(|e| ManifestError::Invalid(path.clone(), e))?;
+
+        return Ok(Some(manifest));
+    }
+
+    /// Every dependency needs exactly one of `path`, `workspace` or `cargo`.
+    fn validate_dependencies(&self) -> Result<(), String> {
+        let tables = [("dependencies".to_string(), &self.dependencies)]
+            .into_iter()
+            .chain(self.target.iter().map(|(selector, it)| {
+                return (
+                    format!("target.'{selector}'.dependencies"),
+                    &it.dependencies,
+                );
+            }));
+
+        for (table, dependencies) in tables {
+            for (name, dependency) in dependencies {
+                let kinds = [
+                    dependency.path.is_some(),
+                    dependency.workspace,
+                    dependency.cargo.is_some(),
+                ];
+
+                if kinds.iter().filter(|it| **it).count() != 1 {
+                    return Err(format!(
+                        "{table}.{name}: needs exactly one of path, workspace or cargo"
+                    ));
+                }
+
+                if dependency.header.is_some() && dependency.path.is_some() {
+                    return Err(format!(
+                        "{table}.{name}.header: only cargo dependencies can generate headers"
+                    ));
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Checks that everything features refer to exists in this manifest.
+    fn validate_features(&self) -> Result<(), String> {
+        let dependency = |name: &str| {
+            return self
+                .dependencies
+                .iter()
+                .chain(self.target.values().flat_map(|it| it.dependencies.iter()))
+                .find(|(it, _)| it.get().as_ref() == name)
+                .map(|(_, it)| it);
+        };
+
+        for (feature, enables) in &self.features {
+            if feature.is_empty()
+                || !feature
+                    .chars()
+                    .all(|it| it.is_ascii_alphanumeric() || it == '_' || it == '-')
+            {
+                return Err(format!("features: {feature:?} is not a valid feature name"));
+            }
+
+            for entry in enables {
+                let valid = if let Some(name) = entry.strip_prefix("dep:") {
+                    dependency(name).is_some_and(|it| it.optional)
+                } else if let Some((name, _)) = entry.split_once('/') {
+                    // Cargo features are set with the dependency's `features` instead.
+                    dependency(name).is_some_and(|it| it.cargo.is_none())
+                } else {
+                    self.features.contains_key(entry)
+                        || dependency(entry).is_some_and(|it| it.optional)
+                };
+
+                if !valid {
+                    return Err(format!(
+                        "features.{feature}: {entry:?} isn't a feature or optional dependency"
+                    ));
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(triple: &str) -> Target {
+        return Target::parse(triple).unwrap();
+    }
+
+    fn cfg(text: &str) -> CfgExpr {
+        return CfgExpr::parse(text).unwrap();
+    }
+
+    #[test]
+    fn parses_cfg_expressions() {
+        assert_eq!(cfg("unix"), CfgExpr::Name("unix".into()));
+
+        assert_eq!(
+            cfg(r#"all(target_os = "linux", not(target_arch = "x86"))"#),
+            CfgExpr::All(vec![
+                CfgExpr::KeyValue("target_os".into(), "linux".into()),
+                CfgExpr::Not(Box::new(CfgExpr::KeyValue(
+                    "target_arch".into(),
+                    "x86".into()
+                ))),
+            ])
+        );
+
+        assert_eq!(
+            cfg(r#"any(windows, target_env = "musl",)"#),
+            CfgExpr::Any(vec![
+                CfgExpr::Name("windows".into()),
+                CfgExpr::KeyValue("target_env".into(), "musl".into()),
+            ])
+        );
+
+        assert_eq!(cfg("all()"), CfgExpr::All(vec![]));
+
+        for text in [
+            r#"any(unix, target_os = "macos")"#,
+            r#"not(all(windows, target_env = "msvc"))"#,
+        ] {
+            assert_eq!(cfg(text).to_string(), text);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_cfg_expressions() {
+        for text in [
+            "",
+            "linux",
+            r#"target_os = linux"#,
+            r#"target_kernel = "linux""#,
+            r#"target_os = "linux"#,
+            "not(unix, windows)",
+            "either(unix)",
+            "any(unix windows)",
+            "unix)",
+            "unix, windows",
+        ] {
+            assert!(CfgExpr::parse(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn evaluates_cfg_expressions() {
+        let linux = target("x86_64-pc-linux-gnu");
+        let windows = target("x86_64-pc-windows-msvc");
+        let mac = target("arm64-apple-macosx14.0");
+        let bare_metal = target("thumbv7em-none-eabihf");
+        let wasm = target("wasm32-unknown-unknown");
+
+        let cases = [
+            ("unix", [true, false, true, false, false]),
+            ("windows", [false, true, false, false, false]),
+            (
+                r#"target_family = "wasm""#,
+                [false, false, false, false, true],
+            ),
+            (r#"target_os = "macos""#, [false, false, true, false, false]),
+            (r#"target_os = "none""#, [false, false, false, true, true]),
+            (
+                r#"target_arch = "aarch64""#,
+                [false, false, true, false, false],
+            ),
+            (r#"target_arch = "arm""#, [false, false, false, true, false]),
+            (r#"target_env = "msvc""#, [false, true, false, false, false]),
+            (r#"target_env = """#, [false, false, true, false, true]),
+            (
+                r#"target_vendor = "apple""#,
+                [false, false, true, false, false],
+            ),
+            (
+                r#"target_pointer_width = "64""#,
+                [true, true, true, false, false],
+            ),
+            (
+                r#"all(unix, not(target_os = "macos"))"#,
+                [true, false, false, false, false],
+            ),
+            ("any(windows, unix)", [true, true, true, false, false]),
+            ("all()", [true; 5]),
+            ("any()", [false; 5]),
+        ];
+
+        for (text, expected) in cases {
+            let expr = cfg(text);
+
+            for (target, expected) in [&linux, &windows, &mac, &bare_metal, &wasm]
+                .into_iter()
+                .zip(expected)
+            {
+                assert_eq!(expr.matches(target), expected, "{text} on {target}");
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_dashed_keys() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "app"
+            output = "executable"
+            public-include = ["api"]
+            c-standard = "c11"
+            gnu-extensions = true
+
+            [dependencies]
+            zlib = { path = "../zlib", default-features = false }
+
+            [build]
+            cpp-stdlib = "libstdc++"
+            cpp-runtime = "static"
+
+            [profile.small]
+            inherits = "release"
+            opt-level = "s"
+            cpp-standard = "c++20"
+            "#,
+        )
+        .unwrap();
+
+        let ManifestKind::Package(package) = &manifest.kind else {
+            panic!("expected a package");
+        };
+
+        assert_eq!(package.public_include, [PathBuf::from("api")]);
+        assert_eq!(package.c_standard, LanguageStandard::parse("c11"));
+        assert_eq!(package.gnu_extensions, Some(true));
+
+        assert!(
+            !manifest
+                .dependencies
+                .values()
+                .next()
+                .unwrap()
+                .default_features
+        );
+
+        assert_eq!(manifest.build.cpp_stdlib, Some(CppStdlib::LibStdCpp));
+        assert_eq!(manifest.build.cpp_runtime, Some(CppRuntime::Static));
+
+        let profile = &manifest.profile["small"];
+
+        assert_eq!(profile.opt_level, Some(OptLevel::Size));
+        assert_eq!(profile.cpp_standard, LanguageStandard::parse("c++20"));
+    }
+
+    #[test]
+    fn validates_package_names() {
+        for name in ["app", "math-lib", "lib_2"] {
+            assert!(validate_package_name(name).is_ok(), "{name}");
+        }
+
+        for name in ["", "obj", "cargo", "../app", "my lib", ".hidden"] {
+            assert!(validate_package_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn parses_target_selectors() {
+        assert_eq!(
+            TargetSelector::parse("cfg(unix)").unwrap(),
+            TargetSelector::Cfg(CfgExpr::Name("unix".into()))
+        );
+
+        assert_eq!(
+            TargetSelector::parse("aarch64-linux-gnu").unwrap(),
+            TargetSelector::Triple(target("aarch64-unknown-linux-gnu"))
+        );
+
+        assert!(TargetSelector::parse("cfg(linux)").is_err());
+        assert!(TargetSelector::parse("not-a-target").is_err());
+    }
+
+    #[test]
+    fn triple_selectors_ignore_the_vendor() {
+        let selector = |text: &str| TargetSelector::parse(text).unwrap();
+
+        let linux = target("x86_64-pc-linux-gnu");
+
+        assert!(selector("x86_64-pc-linux-gnu").matches(&linux));
+        assert!(selector("x86_64-unknown-linux-gnu").matches(&linux));
+        assert!(selector("x86_64-linux-gnu").matches(&linux));
+        assert!(!selector("x86_64-pc-linux-musl").matches(&linux));
+        assert!(!selector("x86_64-pc-linux").matches(&linux));
+        assert!(!selector("aarch64-pc-linux-gnu").matches(&linux));
+        assert!(!selector("x86_64-pc-windows-gnu").matches(&linux));
+
+        let mac = target("arm64-apple-macosx14.0");
+
+        assert!(selector("aarch64-apple-darwin").matches(&mac));
+        assert!(selector("arm64-apple-macos").matches(&mac));
+        assert!(!selector("x86_64-apple-darwin").matches(&mac));
+
+        let bare_metal = target("thumbv7em-none-eabihf");
+
+        assert!(selector("thumbv7em-unknown-none-eabihf").matches(&bare_metal));
+        assert!(!selector("thumbv6m-none-eabi").matches(&bare_metal));
     }
 }
@@ -0,0 +1,246 @@
+//! A compiler backend that doesn't compile anything, for testing builds without a toolchain.
+
+use std::{fs, path::Path, sync::Mutex};
+
+use crate::compiler_backend::{CompilerBackend, ExtraCompileOptions};
+
+/// Something the build asked the mock to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockCall {
+    Compile {
+        source: String,
+        output: String,
+        include_directories: Vec<String>,
+        defines: Vec<String>,
+    },
+    Archive {
+        objects: Vec<String>,
+        output: String,
+    },
+    Link {
+        objects: Vec<String>,
+        output: String,
+        lib_paths: Vec<String>,
+        dynamic_libs: Vec<String>,
+        static_libs: Vec<String>,
+        dynamic_library: bool,
+        cpp: bool,
+    },
+}
+
+impl MockCall {
+    pub fn output(&self) -> &str {
+        return match self {
+            Self::Compile { output, .. }
+            | Self::Archive { output, .. }
+            | Self::Link { output, .. } => output,
+        };
+    }
+}
+
+/// Records every compile, archive and link instead of running them, and writes
+/// placeholder outputs (and depfiles for objects), so up-to-date checks work like usual.
+#[derive(Debug)]
+pub struct MockCompilerBackend {
+    identity: String,
+    calls: Mutex<Vec<MockCall>>,
+    /// Anything reading or writing a path ending in one of these fails.
+    failures: Mutex<Vec<String>>,
+}
+
+impl Default for MockCompilerBackend {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl MockCompilerBackend {
+    pub fn new() -> Self {
+        return Self::with_identity("mock");
+    }
+
+    /// A different identity is like upgrading the compiler, so everything gets rebuilt.
+    pub fn with_identity(identity: &str) -> Self {
+        return Self {
+            identity: identity.to_string(),
+            calls: Mutex::new(vec![]),
+            failures: Mutex::new(vec![]),
+        };
+    }
+
+    /// Makes calls involving a path ending in `suffix` fail, like a compile error would.
+    /// They're still recorded, but their outputs aren't written.
+    pub fn fail_on(&self, suffix: &str) {
+        self.failures.lock().unwrap().push(suffix.to_string());
+    }
+
+    /// Every call so far, in the order they were made.
+    pub fn calls(&self) -> Vec<MockCall> {
+        return self.calls.lock().unwrap().clone();
+    }
+
+    /// Like [`MockCompilerBackend::calls`], but also forgets them.
+    pub fn take_calls(&self) -> Vec<MockCall> {
+        return std::mem::take(&mut self.calls.lock().unwrap());
+    }
+
+    fn record(&self, call: MockCall, paths: &[&str]) -> std::io::Result<()> {
+        let output = call.output().to_string();
+
+        self.calls.lock().unwrap().push(call);
+
+        let failures = self.failures.lock().unwrap();
+
+        if let Some(path) = paths
+            .iter()
+            .chain([&output.as_str()])
+            .find(|path| failures.iter().any(|it| path.ends_with(it.as_str())))
+        {
+            return Err(std::io::Error::other(format!("Mock failure for {path}")));
+        }
+
+        return Ok(());
+    }
+}
+
+fn write_placeholder(output_path: &str, inputs: &[String]) -> std::io::Result<()> {
+    fs::create_dir_all(Path::new(output_path).parent().unwrap())?;
+
+    return fs::write(
+        output_path,
+        format!("mock output of {}\n", inputs.join(" ")),
+    );
+}
+
+impl CompilerBackend for MockCompilerBackend {
+    fn identity(&self) -> &str {
+        return &self.identity;
+    }
+
+    fn compile_object(
+        &self,
+        source_path: &str,
+        output_path: &str,
+        include_directories: &[String],
+        defines: &[String],
+        _extra_options: &ExtraCompileOptions,
+        gen_compile_commands: bool,
+    ) -> std::io::Result<Option<Vec<String>>> {
+        if gen_compile_commands {
+            let mut args = vec!["mock-cc".into(), "-c".into(), source_path.into()];
+
+            args.extend(["-o".into(), output_path.into()]);
+            args.extend(include_directories.iter().map(|it| format!("-I{it}")));
+            args.extend(defines.iter().map(|it| format!("-D{it}")));
+
+            return Ok(Some(args));
+        }
+
+        self.record(
+            MockCall::Compile {
+                source: source_path.into(),
+                output: output_path.into(),
+                include_directories: include_directories.to_vec(),
+                defines: defines.to_vec(),
+            },
+            &[source_path],
+        )?;
+
+        write_placeholder(output_path, &[source_path.into()])?;
+
+        fs::write(
+            Path::new(output_path).with_extension("d"),
+            format!("{output_path}: {source_path}\n"),
+        )?;
+
+        return Ok(None);
+    }
+
+    fn archive_command(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        _extra_options: &ExtraCompileOptions,
+    ) -> Vec<String> {
+        let mut args = vec!["mock-ar".into(), output_path.into()];
+
+        args.extend(object_paths.iter().cloned());
+
+        return args;
+    }
+
+    fn archive_objects(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        _extra_options: &ExtraCompileOptions,
+    ) -> std::io::Result<()> {
+        let paths = object_paths.iter().map(String::as_str).collect::<Vec<_>>();
+
+        self.record(
+            MockCall::Archive {
+                objects: object_paths.to_vec(),
+                output: output_path.into(),
+            },
+            &paths,
+        )?;
+
+        return write_placeholder(output_path, object_paths);
+    }
+
+    fn link_command(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        lib_paths: &[String],
+        dynamic_libs: &[String],
+        static_libs: &[String],
+        dynamic_library: bool,
+        cpp: bool,
+        _extra_options: &ExtraCompileOptions,
+    ) -> Vec<String> {
+        let mut args = vec![if cpp { "mock-c++" } else { "mock-cc" }.to_string()];
+
+        args.extend(object_paths.iter().cloned());
+        args.extend(lib_paths.iter().map(|it| format!("-L{it}")));
+        args.extend(static_libs.iter().cloned());
+        args.extend(dynamic_libs.iter().map(|it| format!("-l{it}")));
+
+        if dynamic_library {
+            args.push("-shared".into());
+        }
+
+        args.extend(["-o".into(), output_path.into()]);
+
+        return args;
+    }
+
+    fn link_objects(
+        &self,
+        object_paths: &[String],
+        output_path: &str,
+        lib_paths: &[String],
+        dynamic_libs: &[String],
+        static_libs: &[String],
+        dynamic_library: bool,
+        cpp: bool,
+        _extra_options: &ExtraCompileOptions,
+    ) -> std::io::Result<()> {
+        let paths = object_paths.iter().map(String::as_str).collect::<Vec<_>>();
+
+        self.record(
+            MockCall::Link {
+                objects: object_paths.to_vec(),
+                output: output_path.into(),
+                lib_paths: lib_paths.to_vec(),
+                dynamic_libs: dynamic_libs.to_vec(),
+                static_libs: static_libs.to_vec(),
+                dynamic_library,
+                cpp,
+            },
+            &paths,
+        )?;
+
+        return write_placeholder(output_path, object_paths);
+    }
+}
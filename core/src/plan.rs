@@ -0,0 +1,714 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::PathBuf,
+};
+
+use crate::{
+    build::{
+        BuildConfig, BuildError, PackageSettings, SOURCE_EXTENSIONS, cargo_target_directory,
+        compiled_path, header_crates, object_path, package_settings,
+    },
+    cargo::{CargoCrate, CrateKind, HeaderTool, find_cargo, rust_system_libs},
+    compiler_backend::{CompilerBackend, ExtraCompileOptions, Language},
+    file_up_to_date,
+    fingerprint::Fingerprint,
+    manifest::PackageKind,
+    output_up_to_date,
+    profile::Profile,
+    project::{Package, Workspace},
+    read_depfile_inputs,
+    target::Target,
+};
+
+pub type ActionId = usize;
+
+/// What an action does, with everything it needs to do it.
+#[derive(Debug, Clone)]
+pub enum ActionKind {
+    Compile {
+        source: String,
+        settings: PackageSettings,
+    },
+    Archive {
+        objects: Vec<String>,
+        extra_options: ExtraCompileOptions,
+    },
+    Link {
+        objects: Vec<String>,
+        libraries: LinkLibraries,
+        dynamic_library: bool,
+        extra_options: ExtraCompileOptions,
+    },
+    /// Runs Cargo for a Rust crate.
+    Cargo {
+        cargo_crate: CargoCrate,
+        cargo: String,
+        target: Target,
+        profile: Profile,
+        target_dir: PathBuf,
+    },
+    /// Generates the C header of a Rust crate.
+    Header {
+        cargo_crate: CargoCrate,
+        tool: HeaderTool,
+        target_dir: PathBuf,
+    },
+}
+
+impl ActionKind {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Self::Compile { .. } => "compile",
+            Self::Archive { .. } => "archive",
+            Self::Link { .. } => "link",
+            Self::Cargo { .. } => "cargo",
+            Self::Header { .. } => "header",
+        };
+    }
+}
+
+/// One step of a build. It only runs once everything in `dependencies` ran.
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub kind: ActionKind,
+    /// The package this is for, `None` for Rust crates.
+    pub package: Option<usize>,
+    /// The command this runs. The built-in header generator doesn't run one, so
+    /// it gets a stand-in that changes when its output would.
+    pub argv: Vec<String>,
+    /// The tool that runs the command, which goes in the fingerprint.
+    pub identity: String,
+    /// Files that aren't in the depfile, but still make the outputs stale when they're newer.
+    pub inputs: Vec<String>,
+    /// The first one is the main output, which the fingerprint belongs to.
+    pub outputs: Vec<String>,
+    pub depfile: Option<String>,
+    pub dependencies: Vec<ActionId>,
+}
+
+impl Action {
+    pub fn output(&self) -> &str {
+        return &self.outputs[0];
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        return Fingerprint::new(&self.identity, &self.argv);
+    }
+
+    /// Whether running this would do nothing, only looking at this action's own files.
+    pub fn up_to_date(&self) -> io::Result<bool> {
+        for output in &self.outputs {
+            if !output_up_to_date(output, &self.inputs)? {
+                return Ok(false);
+            }
+        }
+
+        if let Some(depfile) = &self.depfile
+            && !file_up_to_date(depfile, self.output())?
+        {
+            return Ok(false);
+        }
+
+        return self.fingerprint().matches(self.output());
+    }
+
+    /// The command as it'd be typed in a shell.
+    pub fn command_line(&self) -> String {
+        if let ActionKind::Header {
+            tool: HeaderTool::Builtin,
+            cargo_crate,
+            ..
+        } = &self.kind
+        {
+            return format!(
+                "# generate {} from {} with the built-in scanner",
+                self.output(),
+                cargo_crate.manifest_dir.display()
+            );
+        }
+
+        return self
+            .argv
+            .iter()
+            .map(|it| shell_quote(it))
+            .collect::<Vec<_>>()
+            .join(" ");
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|it| it.is_ascii_alphanumeric() || "-_./=:+,@%".contains(it));
+
+    if plain {
+        return arg.to_string();
+    }
+
+    return format!("'{}'", arg.replace('\'', "'\\''"));
+}
+
+/// Libraries a package needs to be linked against, in the form `link_objects` takes.
+#[derive(Debug, Clone, Default)]
+pub struct LinkLibraries {
+    pub lib_paths: Vec<String>,
+    pub dynamic_libs: Vec<String>,
+    /// Paths of the archives, since the names alone could be found in the wrong directory.
+    pub static_libs: Vec<String>,
+    /// The files behind the dependency libraries, for checking if we need to relink.
+    pub files: Vec<String>,
+    /// Whether the package or a static library it pulls in has C++ in it.
+    pub cpp: bool,
+}
+
+impl LinkLibraries {
+    fn add_system_libs(&mut self, package: &Package) {
+        for library in &package.build.link {
+            if !self.dynamic_libs.contains(library) {
+                self.dynamic_libs.push(library.clone());
+            }
+        }
+    }
+
+    /// Rust libraries are never linked whole, since they carry all of std with them.
+    fn add_cargo_output(&mut self, output: &CargoOutput) {
+        let lib_path = output
+            .artifact
+            .parent()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        if !self.lib_paths.contains(&lib_path) {
+            self.lib_paths.push(lib_path);
+        }
+
+        let file = output.artifact.to_str().unwrap().to_string();
+
+        if !self.files.contains(&file) {
+            self.files.push(file);
+        }
+
+        if !self.dynamic_libs.contains(&output.link_name) {
+            self.dynamic_libs.push(output.link_name.clone());
+        }
+
+        // These need to come after the library, even if something else asked for them first.
+        for library in output.system_libs {
+            self.dynamic_libs.retain(|it| it != library);
+            self.dynamic_libs.push(library.to_string());
+        }
+    }
+}
+
+/// A Rust crate in the plan, shared by every package depending on it.
+struct CargoOutput {
+    artifact: PathBuf,
+    link_name: String,
+    system_libs: &'static [&'static str],
+    action: ActionId,
+    /// Generating the header is its own action, so C code can start compiling before Cargo is done.
+    header_action: Option<ActionId>,
+}
+
+fn modify_filename(
+    package: &Package,
+    compiler: &dyn CompilerBackend,
+    extra_options: &ExtraCompileOptions,
+    base_name: &str,
+) -> String {
+    return match package.output {
+        PackageKind::Executable => match compiler.get_executable_suffix(extra_options) {
+            Some(suffix) => format!("{}.{}", base_name, suffix),
+            None => base_name.to_string(),
+        },
+        PackageKind::DynamicLibrary => format!(
+            "{}{}.{}",
+            compiler.get_library_prefix(extra_options),
+            base_name,
+            compiler.get_dynamic_suffix(extra_options)
+        ),
+        PackageKind::StaticLibrary => format!(
+            "{}{}.{}",
+            compiler.get_library_prefix(extra_options),
+            base_name,
+            compiler.get_static_suffix(extra_options)
+        ),
+    };
+}
+
+pub fn output_path(
+    package: &Package,
+    compiler: &dyn CompilerBackend,
+    settings: &PackageSettings,
+) -> PathBuf {
+    return settings.build_directory.join(modify_filename(
+        package,
+        compiler,
+        &settings.extra_options,
+        &package.name.get(),
+    ));
+}
+
+/// Static libraries only contain their own objects, so we pull in their
+/// dependencies (and system libraries) as well. Dynamic libraries already carry theirs.
+fn collect_link_libraries(
+    workspace: &Workspace,
+    index: usize,
+    outputs: &HashMap<usize, PathBuf>,
+    cargo_outputs: &HashMap<PathBuf, CargoOutput>,
+    libraries: &mut LinkLibraries,
+) -> Result<(), BuildError> {
+    let package = workspace.package(index);
+
+    for cargo_crate in workspace.cargo_dependencies(index) {
+        libraries.add_cargo_output(&cargo_outputs[&cargo_crate.manifest_dir]);
+    }
+
+    for dependency_index in workspace.dependency_indices(index)? {
+        let dependency = workspace.package(dependency_index);
+
+        let name = dependency.name.get().to_string();
+
+        let file = outputs[&dependency_index].to_str().unwrap().to_string();
+
+        if !libraries.files.contains(&file) {
+            libraries.files.push(file.clone());
+        }
+
+        match dependency.output {
+            PackageKind::Executable => {
+                return Err(BuildError::DependsOnExecutable(
+                    package.name.get().to_string(),
+                    name,
+                ));
+            }
+            PackageKind::DynamicLibrary => {
+                let lib_path = outputs[&dependency_index]
+                    .parent()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+
+                if !libraries.lib_paths.contains(&lib_path) {
+                    libraries.lib_paths.push(lib_path);
+                }
+
+                if !libraries.dynamic_libs.contains(&name) {
+                    libraries.dynamic_libs.push(name);
+                }
+            }
+            PackageKind::StaticLibrary => {
+                if !libraries.static_libs.contains(&file) {
+                    libraries.static_libs.push(file);
+                }
+
+                libraries.add_system_libs(dependency);
+
+                libraries.cpp |= dependency
+                    .languages(SOURCE_EXTENSIONS)?
+                    .contains(&Language::Cpp);
+
+                collect_link_libraries(
+                    workspace,
+                    dependency_index,
+                    outputs,
+                    cargo_outputs,
+                    libraries,
+                )?;
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/// Everything a build of some packages would do, as a graph of actions. Nothing
+/// runs when making one, so it's also what `idegen` and dry runs look at.
+#[derive(Debug, Default)]
+pub struct BuildPlan {
+    /// Every action comes after the ones it depends on.
+    pub actions: Vec<Action>,
+    /// The output of every package in the plan.
+    pub outputs: HashMap<usize, PathBuf>,
+    /// What every package gets linked against. Static libraries aren't linked, so
+    /// for those, it's what anything linking them needs on top.
+    pub libraries: HashMap<usize, LinkLibraries>,
+}
+
+impl BuildPlan {
+    /// Plans building `roots` and all of their dependencies.
+    pub fn new(
+        workspace: &Workspace,
+        compiler: &dyn CompilerBackend,
+        config: &BuildConfig,
+        roots: &[usize],
+    ) -> Result<Self, BuildError> {
+        let mut plan = Self::default();
+        let mut package_actions: HashMap<usize, ActionId> = HashMap::new();
+
+        let order = workspace.dependency_order(roots)?;
+
+        let cargo_outputs = plan.add_cargo_crates(workspace, config, &order)?;
+
+        for index in order {
+            let package = workspace.package(index);
+
+            let settings = package_settings(workspace, index, config)?;
+
+            let mut libraries = LinkLibraries::default();
+
+            libraries.add_system_libs(package);
+
+            libraries.cpp = package
+                .languages(SOURCE_EXTENSIONS)?
+                .contains(&Language::Cpp);
+
+            collect_link_libraries(
+                workspace,
+                index,
+                &plan.outputs,
+                &cargo_outputs,
+                &mut libraries,
+            )?;
+
+            let dependencies = workspace
+                .dependency_indices(index)?
+                .iter()
+                .map(|it| package_actions[it])
+                .chain(
+                    workspace
+                        .cargo_dependencies(index)
+                        .iter()
+                        .map(|it| cargo_outputs[&it.manifest_dir].action),
+                )
+                .collect::<Vec<_>>();
+
+            let header_actions = header_crates(workspace, index)?
+                .iter()
+                .filter_map(|it| cargo_outputs[&it.manifest_dir].header_action)
+                .collect::<Vec<_>>();
+
+            let output = output_path(package, compiler, &settings);
+
+            plan.outputs.insert(index, output.clone());
+            plan.libraries.insert(index, libraries.clone());
+
+            let action = plan.add_package(
+                index,
+                package,
+                compiler,
+                settings,
+                libraries,
+                output,
+                &dependencies,
+                &header_actions,
+            )?;
+
+            package_actions.insert(index, action);
+        }
+
+        return Ok(plan);
+    }
+
+    fn add(&mut self, action: Action) -> ActionId {
+        self.actions.push(action);
+
+        return self.actions.len() - 1;
+    }
+
+    /// Adds an action for every object in the package, which waits on `header_actions`,
+    /// and one for linking (or archiving) them, which waits on those and on `dependencies`.
+    /// Returns the id of the last one.
+    #[allow(clippy::too_many_arguments)]
+    fn add_package(
+        &mut self,
+        index: usize,
+        package: &Package,
+        compiler: &dyn CompilerBackend,
+        settings: PackageSettings,
+        libraries: LinkLibraries,
+        output: PathBuf,
+        dependencies: &[ActionId],
+        header_actions: &[ActionId],
+    ) -> Result<ActionId, BuildError> {
+        let compiled = compiled_path(&settings, &package.name.get());
+
+        let mut objects = vec![];
+        let mut object_actions = dependencies.to_vec();
+
+        package.visit(
+            |path| {
+                let base_path = object_path(&compiled, path, compiler, &settings.extra_options);
+                let output = base_path.to_str().unwrap().to_string();
+
+                let source = package.resolve_source(path);
+
+                let argv = compiler
+                    .compile_object(
+                        &source,
+                        &output,
+                        &settings.include_directories,
+                        &settings.defines,
+                        &settings.extra_options,
+                        true,
+                    )?
+                    .unwrap();
+
+                objects.push(output.clone());
+
+                object_actions.push(self.add(Action {
+                    kind: ActionKind::Compile {
+                        source,
+                        settings: settings.clone(),
+                    },
+                    package: Some(index),
+                    argv,
+                    identity: compiler.identity().to_string(),
+                    inputs: vec![],
+                    outputs: vec![output],
+                    depfile: Some(base_path.with_extension("d").to_str().unwrap().to_string()),
+                    dependencies: header_actions.to_vec(),
+                }));
+
+                return Ok(());
+            },
+            SOURCE_EXTENSIONS,
+        )?;
+
+        let output = output.to_str().unwrap().to_string();
+
+        let extra_options = settings.extra_options;
+
+        let action = if let PackageKind::StaticLibrary = package.output {
+            Action {
+                argv: compiler.archive_command(&objects, &output, &extra_options),
+                identity: compiler.identity().to_string(),
+                inputs: objects.clone(),
+                outputs: vec![output],
+                depfile: None,
+                package: Some(index),
+                dependencies: object_actions,
+                kind: ActionKind::Archive {
+                    objects,
+                    extra_options,
+                },
+            }
+        } else {
+            let dynamic_library = package.output == PackageKind::DynamicLibrary;
+
+            let mut inputs = objects.clone();
+
+            inputs.extend(libraries.files.iter().cloned());
+
+            Action {
+                argv: compiler.link_command(
+                    &objects,
+                    &output,
+                    &libraries.lib_paths,
+                    &libraries.dynamic_libs,
+                    &libraries.static_libs,
+                    dynamic_library,
+                    libraries.cpp,
+                    &extra_options,
+                ),
+                identity: compiler.identity().to_string(),
+                inputs,
+                outputs: vec![output],
+                depfile: None,
+                package: Some(index),
+                dependencies: object_actions,
+                kind: ActionKind::Link {
+                    objects,
+                    libraries,
+                    dynamic_library,
+                    extra_options,
+                },
+            }
+        };
+
+        return Ok(self.add(action));
+    }
+
+    /// Adds an action running Cargo for every Rust crate `order` depends on. Crates used by
+    /// more than one package are built once, with the features of all of them.
+    fn add_cargo_crates(
+        &mut self,
+        workspace: &Workspace,
+        config: &BuildConfig,
+        order: &[usize],
+    ) -> Result<HashMap<PathBuf, CargoOutput>, BuildError> {
+        let mut crates: HashMap<PathBuf, CargoCrate> = HashMap::new();
+
+        for index in order {
+            for cargo_crate in workspace.cargo_dependencies(*index) {
+                crates
+                    .entry(cargo_crate.manifest_dir.clone())
+                    .and_modify(|it| it.unify(cargo_crate))
+                    .or_insert_with(|| cargo_crate.clone());
+            }
+        }
+
+        let mut outputs = HashMap::new();
+
+        if crates.is_empty() {
+            return Ok(outputs);
+        }
+
+        let cargo = find_cargo()?.to_str().unwrap().to_string();
+
+        let build_directory = &config.build_directory;
+        let target_dir = cargo_target_directory(build_directory);
+
+        let target = &config.target;
+        let profile = &config.profile;
+
+        // Sorted, so the plan comes out the same every time.
+        let mut crates = crates.into_iter().collect::<Vec<_>>();
+
+        crates.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (manifest_dir, cargo_crate) in crates {
+            let built = cargo_crate.artifact_path(target, profile, &target_dir);
+
+            // Dynamic ones get copied next to what we build, where the loader looks for them.
+            let artifact = match cargo_crate.kind {
+                CrateKind::Static => built.clone(),
+                CrateKind::Dynamic => build_directory.join(cargo_crate.file_name(target)),
+            };
+
+            let system_libs = match cargo_crate.kind {
+                CrateKind::Static => rust_system_libs(target),
+                CrateKind::Dynamic => &[],
+            };
+
+            let header_action = match cargo_crate.header {
+                Some(generator) => Some(self.add_header(
+                    cargo_crate.clone(),
+                    generator.find_tool()?,
+                    target_dir.clone(),
+                )),
+                None => None,
+            };
+
+            let mut outputs_of_action = vec![built.to_str().unwrap().to_string()];
+
+            if built != artifact {
+                outputs_of_action.push(artifact.to_str().unwrap().to_string());
+            }
+
+            // Cargo's depfile has every source, but not the manifest or lockfile.
+            let manifests = ["Cargo.toml", "Cargo.lock"]
+                .iter()
+                .map(|it| cargo_crate.manifest_dir.join(it))
+                .filter(|it| it.exists())
+                .map(|it| it.to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+
+            let action = self.add(Action {
+                argv: cargo_crate.build_command(&cargo, target, profile, &target_dir),
+                identity: cargo.clone(),
+                inputs: manifests,
+                outputs: outputs_of_action,
+                depfile: Some(
+                    cargo_crate
+                        .depfile_path(target, profile, &target_dir)
+                        .to_str()
+                        .unwrap()
+                        .to_string(),
+                ),
+                package: None,
+                dependencies: vec![],
+                kind: ActionKind::Cargo {
+                    cargo_crate: cargo_crate.clone(),
+                    cargo: cargo.clone(),
+                    target: target.clone(),
+                    profile: profile.clone(),
+                    target_dir: target_dir.clone(),
+                },
+            });
+
+            outputs.insert(
+                manifest_dir,
+                CargoOutput {
+                    artifact,
+                    link_name: cargo_crate.link_name(target),
+                    system_libs,
+                    action,
+                    header_action,
+                },
+            );
+        }
+
+        return Ok(outputs);
+    }
+
+    fn add_header(
+        &mut self,
+        cargo_crate: CargoCrate,
+        tool: HeaderTool,
+        target_dir: PathBuf,
+    ) -> ActionId {
+        let header = cargo_crate.header_path(&target_dir);
+
+        // New sources don't show up in the depfile, but they do change the directory.
+        let inputs = [
+            cargo_crate.manifest_dir.join("Cargo.toml"),
+            cargo_crate.manifest_dir.join("src"),
+        ]
+        .iter()
+        .map(|it| it.to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+
+        return self.add(Action {
+            argv: cargo_crate.header_command(&tool, &target_dir),
+            identity: "header".into(),
+            inputs,
+            outputs: vec![header.to_str().unwrap().to_string()],
+            depfile: Some(
+                cargo_crate
+                    .header_depfile_path(&target_dir)
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            ),
+            package: None,
+            dependencies: vec![],
+            kind: ActionKind::Header {
+                cargo_crate,
+                tool,
+                target_dir,
+            },
+        });
+    }
+
+    /// The actions running the plan would run: the ones that aren't up to date, and
+    /// everything depending on them.
+    pub fn pending(&self) -> io::Result<Vec<ActionId>> {
+        let mut pending = vec![false; self.actions.len()];
+
+        for (id, action) in self.actions.iter().enumerate() {
+            pending[id] =
+                action.dependencies.iter().any(|it| pending[*it]) || !action.up_to_date()?;
+        }
+
+        return Ok((0..self.actions.len()).filter(|it| pending[*it]).collect());
+    }
+
+    /// Every file the build read, according to the depfiles of the actions that ran.
+    pub fn inputs(&self) -> io::Result<BTreeSet<String>> {
+        let mut inputs = BTreeSet::new();
+
+        for depfile in self.actions.iter().filter_map(|it| it.depfile.as_ref()) {
+            if fs::exists(depfile)? {
+                inputs.extend(read_depfile_inputs(depfile)?);
+            }
+        }
+
+        return Ok(inputs);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use crate::{
+    compiler_backend::{LanguageStandard, OptLevel},
+    manifest::ProfileManifest,
+    project::WorkspaceError,
+};
+
+/// A fully resolved profile, with inheritance applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub opt_level: OptLevel,
+    pub debug: bool,
+    pub lto: bool,
+    pub assertions: bool,
+    pub defines: Vec<String>,
+    pub c_standard: Option<LanguageStandard>,
+    pub cpp_standard: Option<LanguageStandard>,
+    pub gnu_extensions: Option<bool>,
+}
+
+impl Profile {
+    pub const DEV: &str = "dev";
+    pub const RELEASE: &str = "release";
+
+    pub fn dev() -> Self {
+        return Self {
+            name: Self::DEV.into(),
+            opt_level: OptLevel::Zero,
+            debug: true,
+            lto: false,
+            assertions: true,
+            defines: vec![],
+            c_standard: None,
+            cpp_standard: None,
+            gnu_extensions: None,
+        };
+    }
+
+    pub fn release() -> Self {
+        return Self {
+            name: Self::RELEASE.into(),
+            opt_level: OptLevel::Three,
+            debug: false,
+            lto: false,
+            assertions: false,
+            defines: vec![],
+            c_standard: None,
+            cpp_standard: None,
+            gnu_extensions: None,
+        };
+    }
+
+    fn builtin(name: &str) -> Option<Self> {
+        return match name {
+            Self::DEV => Some(Self::dev()),
+            Self::RELEASE => Some(Self::release()),
+            _ => None,
+        };
+    }
+
+    /// Resolves the profile called `name`, from the `[profile]` tables in the
+    /// root manifest. `dev` and `release` exist even if they aren't in there.
+    pub fn resolve(
+        name: &str,
+        manifests: &HashMap<String, ProfileManifest>,
+    ) -> Result<Self, WorkspaceError> {
+        return Self::resolve_inner(name, manifests, &mut vec![]);
+    }
+
+    fn resolve_inner(
+        name: &str,
+        manifests: &HashMap<String, ProfileManifest>,
+        stack: &mut Vec<String>,
+    ) -> Result<Self, WorkspaceError> {
+        if stack.iter().any(|it| it == name) {
+            let mut cycle = stack.clone();
+            cycle.push(name.to_string());
+
+            return Err(WorkspaceError::ProfileCycle(cycle));
+        }
+
+        let Some(manifest) = manifests.get(name) else {
+            return Self::builtin(name)
+                .ok_or_else(|| WorkspaceError::ProfileNotFound(name.to_string()));
+        };
+
+        stack.push(name.to_string());
+
+        let mut profile = match &manifest.inherits {
+            Some(parent) => Self::resolve_inner(parent, manifests, stack)?,
+            None => Self::builtin(name)
+                .ok_or_else(|| WorkspaceError::ProfileMissingInherits(name.to_string()))?,
+        };
+
+        stack.pop();
+
+        profile.name = name.to_string();
+
+        if let Some(opt_level) = manifest.opt_level {
+            profile.opt_level = opt_level;
+        }
+
+        if let Some(debug) = manifest.debug {
+            profile.debug = debug;
+        }
+
+        if let Some(lto) = manifest.lto {
+            profile.lto = lto;
+        }
+
+        if let Some(assertions) = manifest.assertions {
+            profile.assertions = assertions;
+        }
+
+        if let Some(defines) = &manifest.defines {
+            profile.defines = defines.clone();
+        }
+
+        if manifest.c_standard.is_some() {
+            profile.c_standard = manifest.c_standard;
+        }
+
+        if manifest.cpp_standard.is_some() {
+            profile.cpp_standard = manifest.cpp_standard;
+        }
+
+        if manifest.gnu_extensions.is_some() {
+            profile.gnu_extensions = manifest.gnu_extensions;
+        }
+
+        return Ok(profile);
+    }
+
+    /// The profile's own defines, plus `NDEBUG` if assertions are off.
+    pub fn all_defines(&self) -> Vec<String> {
+        let mut defines = self.defines.clone();
+
+        if !self.assertions {
+            defines.push("NDEBUG".into());
+        }
+
+        return defines;
+    }
+}
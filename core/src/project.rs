@@ -1,8 +1,22 @@
-use std::{collections::HashMap, ffi::OsStr, io, ops::Deref, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ffi::OsStr,
+    io,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 
 use crate::{
+    BUILD_DIR,
+    cargo::CargoCrate,
+    compiler_backend::{CompilerKind, CppRuntime, CppStdlib, Language, LanguageStandard},
     interner::StrReference,
-    manifest::{Dependency, Manifest, ManifestError, ManifestKind, PackageKind, WorkspaceManifest},
+    manifest::{
+        BuildManifest, Dependency, Manifest, ManifestError, ManifestKind, PackageKind,
+        ProfileManifest, TargetManifest, TargetSelector, WorkspaceManifest,
+    },
+    profile::Profile,
+    target::Target,
     walk_dir,
 };
 
@@ -10,17 +24,56 @@ use crate::{
 pub struct Package {
     pub name: StrReference,
     pub path: PathBuf,
+    /// In name order, the ones from target sections last, so argv built from them
+    /// is the same every time the workspace gets loaded.
     pub dependencies: Box<[StrReference]>,
     pub output: PackageKind,
     pub lto: bool,
+    pub c_standard: Option<LanguageStandard>,
+    pub cpp_standard: Option<LanguageStandard>,
+    pub gnu_extensions: Option<bool>,
+    /// Absolute paths, only the ones that exist.
+    pub public_include: Box<[PathBuf]>,
+    /// Absolute paths, only the ones that exist.
+    pub private_include: Box<[PathBuf]>,
+    /// Include paths in here are absolute. Includes the target sections matching
+    /// [`Workspace::target`].
+    pub build: BuildManifest,
+    /// The `[features]` table.
+    pub features: HashMap<String, Vec<String>>,
+    /// How each dependency was declared, including the ones in matching target sections.
+    pub dependency_manifests: BTreeMap<StrReference, Dependency>,
+    /// Set by [`Workspace::resolve_features`].
+    pub enabled_features: BTreeSet<String>,
+    /// Optional dependencies turned on by a feature.
+    pub(crate) enabled_dependencies: HashSet<StrReference>,
+    /// Dependencies that are Rust crates. Their names are in `dependencies` too.
+    pub cargo_crates: BTreeMap<StrReference, CargoCrate>,
 }
 
 #[derive(Debug)]
 pub struct Workspace {
     pub members: Box<[Package]>,
+    /// Path dependencies that aren't members of the workspace. These are indexed
+    /// after the members, see [`Workspace::package`].
+    pub external_packages: Box<[Package]>,
     pub current_member: Option<usize>,
+    /// Members built when no member is selected and the CWD isn't inside one.
+    pub default_members: Box<[usize]>,
     pub path: PathBuf,
     pub dependencies: im::HashMap<StrReference, Dependency>,
+    /// The `[profile]` tables of the root manifest.
+    pub profiles: HashMap<String, ProfileManifest>,
+    /// What the packages were loaded for, see [`Workspace::load`].
+    pub target: Target,
+    /// `build.cpp_stdlib` of the root manifest.
+    pub cpp_stdlib: Option<CppStdlib>,
+    /// `build.cpp_runtime` of the root manifest.
+    pub cpp_runtime: CppRuntime,
+    /// `build.compiler` of the root manifest.
+    pub compiler: CompilerKind,
+    /// The `[target]` tables of the root manifest, with absolute sysroots.
+    pub targets: HashMap<TargetSelector, TargetManifest>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -45,32 +98,96 @@ pub enum WorkspaceError {
     WorkspaceDependencyNotInWorkspace(String, PathBuf),
     #[error("Dependency named {0} in workspace ({1}) does not match dependency in package ({2}).")]
     MismatchedDependency(String, PathBuf, PathBuf),
+    #[error("Dependency named {0} in package ({1}) is declared with more than one path.")]
+    ConflictingDependency(String, PathBuf),
+    #[error("Invalid manifest ({0}): {1}")]
+    InvalidManifest(PathBuf, String),
+    #[error("Profile {0} not found.")]
+    ProfileNotFound(String),
+    #[error("Profile {0} needs to inherit from another profile.")]
+    ProfileMissingInherits(String),
+    #[error("Profile inheritance cycle detected: {}", .0.join(" -> "))]
+    ProfileCycle(Vec<String>),
+    #[error(
+        "No package selected in workspace ({0}). Run this inside a package, pass --project or --workspace, or set default_members."
+    )]
+    NoMemberSelected(PathBuf),
+    #[error("Dependency cycle detected: {}", .0.join(" -> "))]
+    DependencyCycle(Vec<String>),
+    #[error("Package {1} has no feature named {0}.")]
+    FeatureNotFound(String, String),
+    #[error("Invalid Cargo crate ({0}): {1}.")]
+    InvalidCargoCrate(PathBuf, String),
+    #[error("Packages at {1} and {2} are both named {0}.")]
+    DuplicatePackage(String, PathBuf, PathBuf),
 }
 
 impl Package {
     pub fn load(
-        path: &PathBuf,
+        path: &Path,
         workspace_path: Option<&PathBuf>,
+        target: &Target,
         dependencies: &mut HashMap<StrReference, Dependency>,
     ) -> Result<Option<Self>, WorkspaceError> {
         let Some(manifest) = Manifest::load(path)? else {
             return Ok(None);
         };
 
-        return Self::from_manifest(path, manifest, workspace_path, dependencies);
+        return Self::from_manifest(path, manifest, workspace_path, target, dependencies).map(Some);
     }
 
+    /// Only the `[target.<...>]` sections matching `target` are used, so different
+    /// targets can point a dependency at different paths.
     fn from_manifest(
-        path: &PathBuf,
+        path: &Path,
         manifest: Manifest,
         workspace_path: Option<&PathBuf>,
+        target: &Target,
         dependencies: &mut HashMap<StrReference, Dependency>,
-    ) -> Result<Option<Self>, WorkspaceError> {
+    ) -> Result<Self, WorkspaceError> {
         let ManifestKind::Package(package) = manifest.kind else {
-            return Err(WorkspaceError::ExpectedPackage(path.clone()));
+            return Err(WorkspaceError::ExpectedPackage(path.to_path_buf()));
         };
 
-        for (name, dep) in &manifest.dependencies {
+        let mut sections: Vec<_> = manifest
+            .target
+            .into_iter()
+            .filter(|(selector, _)| selector.matches(target))
+            .collect();
+
+        // HashMap order isn't stable, so sort to always merge them the same way.
+        sections.sort_by_key(|(selector, _)| selector.to_string());
+
+        let all_dependencies = manifest
+            .dependencies
+            .iter()
+            .chain(sections.iter().flat_map(|(_, it)| it.dependencies.iter()));
+
+        let mut cargo_crates = BTreeMap::new();
+
+        // `workspace = true` entries, with the features of the workspace's entry added.
+        let mut inherited = BTreeMap::new();
+
+        for (name, dep) in all_dependencies {
+            if let Some(cargo) = &dep.cargo {
+                let real_path = path.join(cargo);
+
+                let Ok(real_path) = real_path.canonicalize() else {
+                    return Err(WorkspaceError::PackageNotFound(real_path));
+                };
+
+                let cargo_crate = CargoCrate::load(
+                    &real_path,
+                    dep.features.clone(),
+                    dep.default_features,
+                    dep.header,
+                )?;
+
+                cargo_crates.insert(name.clone(), cargo_crate);
+
+                continue;
+            }
+
             if dep.workspace
                 && let Some(workspace_path) = workspace_path
                 && !dependencies.contains_key(name)
@@ -84,41 +201,123 @@ impl Package {
             if dep.workspace && workspace_path.is_none() {
                 return Err(WorkspaceError::WorkspaceDependencyNotInWorkspace(
                     name.get().to_string(),
-                    path.clone(),
+                    path.to_path_buf(),
                 ));
             } else if dep.workspace {
+                let workspace_dep = &dependencies[name];
+
+                let mut features = workspace_dep.features.clone();
+
+                features.extend(dep.features.iter().cloned());
+
+                let default_features = workspace_dep.default_features && dep.default_features;
+
+                // The workspace's path is absolute already.
+                if let Some(cargo) = &workspace_dep.cargo {
+                    let cargo_crate = CargoCrate::load(
+                        cargo,
+                        features.clone(),
+                        default_features,
+                        dep.header.or(workspace_dep.header),
+                    )?;
+
+                    cargo_crates.insert(name.clone(), cargo_crate);
+                }
+
+                inherited.entry(name.clone()).or_insert(Dependency {
+                    features,
+                    default_features,
+                    ..dep.clone()
+                });
+
                 continue;
             }
 
             let real_path = path.join(&*dep.path.clone().unwrap());
 
-            if dependencies.contains_key(name) {
-                let workspace_dep = &dependencies[name];
+            let Ok(real_path) = real_path.canonicalize() else {
+                return Err(WorkspaceError::PackageNotFound(real_path));
+            };
 
-                if workspace_dep.path.clone().unwrap() != real_path {
-                    return Err(WorkspaceError::MismatchedDependency(
+            if let Some(existing) = dependencies.get(name)
+                && existing.path.as_ref() != Some(&real_path)
+            {
+                let Some(workspace_path) = workspace_path else {
+                    return Err(WorkspaceError::ConflictingDependency(
                         name.get().to_string(),
-                        workspace_path.unwrap().clone(),
-                        path.clone(),
+                        path.to_path_buf(),
                     ));
-                }
+                };
+
+                return Err(WorkspaceError::MismatchedDependency(
+                    name.get().to_string(),
+                    workspace_path.clone(),
+                    path.to_path_buf(),
+                ));
             }
 
-            dependencies.insert(name.clone(), Dependency { path: Some(real_path), workspace: false });
+            // The workspace's entry keeps its features for the packages inheriting it.
+            dependencies
+                .entry(name.clone())
+                .or_insert_with(|| Dependency {
+                    path: Some(real_path),
+                    workspace: false,
+                    ..dep.clone()
+                });
         }
 
-        return Ok(Some(Self {
-            name: package.name,
-            path: path.canonicalize()?,
-            dependencies: manifest
-                .dependencies
-                .keys()
-                .map(Clone::clone)
+        let path = path.canonicalize()?;
+
+        let resolve_includes = |dirs: &[PathBuf]| {
+            return dirs
+                .iter()
+                .filter_map(|it| path.join(it).canonicalize().ok())
                 .collect::<Vec<_>>()
-                .into_boxed_slice(),
+                .into_boxed_slice();
+        };
+
+        let absolute_build = |mut build: BuildManifest| {
+            build.include = build.include.iter().map(|it| path.join(it)).collect();
+
+            return build;
+        };
+
+        let mut build = manifest.build;
+        let mut dependencies = manifest.dependencies.keys().cloned().collect::<Vec<_>>();
+        let mut dependency_manifests = manifest.dependencies;
+
+        for (_, section) in sections {
+            build.merge(&section.build);
+
+            for (name, dependency) in section.dependencies {
+                if !dependencies.contains(&name) {
+                    dependencies.push(name.clone());
+                }
+
+                dependency_manifests.entry(name).or_insert(dependency);
+            }
+        }
+
+        dependency_manifests.extend(inherited);
+
+        return Ok(Self {
+            name: package.name,
+            build: absolute_build(build),
+            public_include: resolve_includes(&package.public_include),
+            private_include: resolve_includes(&package.private_include),
+            path,
+            dependencies: dependencies.into_boxed_slice(),
+            features: manifest.features,
+            dependency_manifests,
+            enabled_features: BTreeSet::new(),
+            enabled_dependencies: HashSet::new(),
+            cargo_crates,
             output: package.output,
             lto: package.lto,
-        }));
+            c_standard: package.c_standard,
+            cpp_standard: package.cpp_standard,
+            gnu_extensions: package.gnu_extensions,
+        });
     }
 
     pub fn resolve_source(&self, name: &str) -> String {
@@ -131,6 +330,22 @@ impl Package {
             .to_string();
     }
 
+    /// The languages of the package's sources.
+    pub fn languages(&self, exts: &[&str]) -> io::Result<BTreeSet<Language>> {
+        let mut languages = BTreeSet::new();
+
+        self.visit(
+            |file| {
+                languages.extend(Language::of_source(file));
+
+                return Ok(());
+            },
+            exts,
+        )?;
+
+        return Ok(languages);
+    }
+
     pub fn visit<F: FnMut(&str) -> io::Result<()>>(
         &self,
         mut consumer: F,
@@ -169,6 +384,7 @@ impl From<ManifestError> for WorkspaceError {
         return match value {
             ManifestError::Io(io) => io.into(),
             ManifestError::Toml(toml) => toml.into(),
+            ManifestError::Invalid(path, message) => Self::InvalidManifest(path, message),
         };
     }
 }
@@ -178,15 +394,17 @@ impl Workspace {
         manifest: WorkspaceManifest,
         mut dependencies: HashMap<StrReference, Dependency>,
         path: PathBuf,
+        target: Target,
     ) -> Result<Self, WorkspaceError> {
-        let mut packages = vec![];
+        let mut packages: Vec<Package> = vec![];
 
         for package in manifest.members {
             let mut package_path = path.clone();
 
             package_path = package_path.join(&*package.get());
 
-            let Some(package) = Package::load(&package_path, Some(&path), &mut dependencies)?
+            let Some(package) =
+                Package::load(&package_path, Some(&path), &target, &mut dependencies)?
             else {
                 return Err(WorkspaceError::PackageNotFound(package_path));
             };
@@ -194,15 +412,54 @@ impl Workspace {
             packages.push(package);
         }
 
+        let mut default_members = vec![];
+
+        for member in manifest.default_members {
+            let member_path = path.join(&*member.get());
+
+            let index = member_path
+                .canonicalize()
+                .ok()
+                .and_then(|it| packages.iter().position(|package| package.path == it));
+
+            let Some(index) = index else {
+                return Err(WorkspaceError::CannotFindMember(
+                    member.get().to_string(),
+                    path.clone(),
+                ));
+            };
+
+            default_members.push(index);
+        }
+
         return Ok(Self {
             path: path.canonicalize()?,
             dependencies: dependencies.into(),
             members: packages.into_boxed_slice(),
+            external_packages: [].into(),
             current_member: None,
+            default_members: default_members.into_boxed_slice(),
+            profiles: HashMap::new(),
+            target,
+            cpp_stdlib: None,
+            cpp_runtime: CppRuntime::default(),
+            compiler: CompilerKind::default(),
+            targets: HashMap::new(),
         });
     }
 
-    fn find_first_workspace(mut path: PathBuf) -> Result<Option<Self>, WorkspaceError> {
+    /// The target given on the command line, falling back to `build.target`, then the host.
+    fn select_target(requested: Option<&Target>, build: &BuildManifest) -> Target {
+        return requested
+            .or(build.target.as_ref())
+            .cloned()
+            .unwrap_or_else(Target::host);
+    }
+
+    fn find_first_workspace(
+        mut path: PathBuf,
+        target: Option<&Target>,
+    ) -> Result<Option<Self>, WorkspaceError> {
         loop {
             let manifest = Manifest::load(&path)?;
 
@@ -212,27 +469,42 @@ impl Workspace {
                 let mut dependencies = HashMap::new();
 
                 for (name, dep) in manifest.dependencies {
-                    if dep.workspace || dep.path.is_none() {
+                    if dep.workspace {
                         return Err(WorkspaceError::InvalidDependency(
                             name.get().to_string(),
                             path,
                         ));
                     }
 
-                    let dep_path = dep.path.unwrap();
+                    let dep_path = path.join(dep.path.as_ref().or(dep.cargo.as_ref()).unwrap());
 
-                    let dep_path = path.join(dep_path);
+                    let Ok(dep_path) = dep_path.canonicalize() else {
+                        return Err(WorkspaceError::PackageNotFound(dep_path));
+                    };
 
-                    dependencies.insert(
-                        name,
+                    let dependency = if dep.cargo.is_some() {
+                        Dependency {
+                            cargo: Some(dep_path),
+                            ..dep
+                        }
+                    } else {
                         Dependency {
                             path: Some(dep_path),
                             workspace: false,
-                        },
-                    );
+                            ..dep
+                        }
+                    };
+
+                    dependencies.insert(name, dependency);
                 }
 
-                return Ok(Some(Self::from(ws, dependencies, path)?));
+                let target = Self::select_target(target, &manifest.build);
+
+                let mut workspace = Self::from(ws, dependencies, path, target)?;
+
+                workspace.read_root_settings(manifest.profile, manifest.build, manifest.target);
+
+                return Ok(Some(workspace));
             }
 
             if !path.pop() {
@@ -243,10 +515,23 @@ impl Workspace {
         return Ok(None);
     }
 
-    fn find_first_package(mut path: PathBuf) -> Result<Option<Self>, WorkspaceError> {
+    fn find_first_package(
+        mut path: PathBuf,
+        target: Option<&Target>,
+    ) -> Result<Option<Self>, WorkspaceError> {
         loop {
-            let mut dependencies = HashMap::new();
-            if let Some(package) = Package::load(&path, None, &mut dependencies)? {
+            if let Some(manifest) = Manifest::load(&path)? {
+                let target = Self::select_target(target, &manifest.build);
+
+                let mut dependencies = HashMap::new();
+                let package = Package::from_manifest(
+                    &path,
+                    manifest.clone(),
+                    None,
+                    &target,
+                    &mut dependencies,
+                )?;
+
                 for dependency in &dependencies {
                     if dependency.1.workspace {
                         return Err(WorkspaceError::InvalidDependency(
@@ -256,12 +541,24 @@ impl Workspace {
                     }
                 }
 
-                return Ok(Some(Self {
+                let mut workspace = Self {
                     path: path.canonicalize()?,
                     dependencies: dependencies.into(),
                     members: [package].into(),
+                    external_packages: [].into(),
                     current_member: Some(0),
-                }));
+                    default_members: [].into(),
+                    profiles: HashMap::new(),
+                    target,
+                    cpp_stdlib: None,
+                    cpp_runtime: CppRuntime::default(),
+                    compiler: CompilerKind::default(),
+                    targets: HashMap::new(),
+                };
+
+                workspace.read_root_settings(manifest.profile, manifest.build, manifest.target);
+
+                return Ok(Some(workspace));
             }
 
             if !path.pop() {
@@ -272,6 +569,24 @@ impl Workspace {
         return Ok(None);
     }
 
+    /// Takes the parts of the root manifest that apply to the whole build.
+    fn read_root_settings(
+        &mut self,
+        profiles: HashMap<String, ProfileManifest>,
+        build: BuildManifest,
+        mut targets: HashMap<TargetSelector, TargetManifest>,
+    ) {
+        for target in targets.values_mut() {
+            target.sysroot = target.sysroot.as_ref().map(|it| self.path.join(it));
+        }
+
+        self.profiles = profiles;
+        self.cpp_stdlib = build.cpp_stdlib;
+        self.cpp_runtime = build.cpp_runtime.unwrap_or_default();
+        self.compiler = build.compiler.unwrap_or_default();
+        self.targets = targets;
+    }
+
     fn map_expected_package_to_none(
         value: Result<Option<Self>, WorkspaceError>,
     ) -> Result<Option<Self>, WorkspaceError> {
@@ -282,11 +597,317 @@ impl Workspace {
         return value;
     }
 
-    pub fn load(
-        path: PathBuf,
-        selected_project: Option<String>,
-    ) -> Result<Option<Self>, WorkspaceError> {
-        if let Some(mut workspace) = Self::find_first_workspace(path.clone())? {
+    /// Loads every path dependency that isn't a workspace member, including
+    /// dependencies of those dependencies.
+    fn load_external_packages(&mut self) -> Result<(), WorkspaceError> {
+        let mut dependencies: HashMap<StrReference, Dependency> =
+            self.dependencies.clone().into_iter().collect();
+
+        let mut external: Vec<Package> = vec![];
+
+        loop {
+            // The smallest path, so external packages get the same indices every time.
+            let missing = dependencies
+                .values()
+                .filter_map(|it| it.path.clone())
+                .filter(|path| {
+                    !self
+                        .members
+                        .iter()
+                        .chain(external.iter())
+                        .any(|it| &it.path == path)
+                })
+                .min();
+
+            let Some(missing) = missing else {
+                break;
+            };
+
+            let Some(package) =
+                Package::load(&missing, Some(&self.path), &self.target, &mut dependencies)?
+            else {
+                return Err(WorkspaceError::PackageNotFound(missing));
+            };
+
+            external.push(package);
+        }
+
+        self.dependencies = dependencies.into();
+        self.external_packages = external.into_boxed_slice();
+
+        return Ok(());
+    }
+
+    /// Where everything built for `target` with `profile` goes. Kept separate, so
+    /// switching between them never reuses objects built with other flags.
+    pub fn build_directory(&self, target: &Target, profile: &str) -> PathBuf {
+        return self
+            .path
+            .join(BUILD_DIR)
+            .join(target.to_string())
+            .join(profile);
+    }
+
+    /// The sysroot and linker for `target`. A `[target.<triple>]` table wins over `cfg(...)` ones.
+    pub fn target_settings(&self, target: &Target) -> TargetManifest {
+        let mut matching: Vec<_> = self
+            .targets
+            .iter()
+            .filter(|(selector, _)| selector.matches(target))
+            .collect();
+
+        // HashMap order isn't stable, so sort to always pick the same `cfg(...)` table.
+        matching.sort_by_key(|(selector, _)| {
+            return (
+                !matches!(selector, TargetSelector::Triple(_)),
+                selector.to_string(),
+            );
+        });
+
+        let mut settings = TargetManifest::default();
+
+        for (_, target) in matching {
+            settings.sysroot = settings.sysroot.or_else(|| target.sysroot.clone());
+            settings.linker = settings.linker.or_else(|| target.linker.clone());
+        }
+
+        return settings;
+    }
+
+    pub fn profile(&self, name: &str) -> Result<Profile, WorkspaceError> {
+        return Profile::resolve(name, &self.profiles);
+    }
+
+    /// Members and external packages, in index order.
+    pub fn packages(&self) -> impl Iterator<Item = &Package> {
+        return self.members.iter().chain(self.external_packages.iter());
+    }
+
+    pub fn package(&self, index: usize) -> &Package {
+        if index < self.members.len() {
+            return &self.members[index];
+        }
+
+        return &self.external_packages[index - self.members.len()];
+    }
+
+    pub fn package_mut(&mut self, index: usize) -> &mut Package {
+        if index < self.members.len() {
+            return &mut self.members[index];
+        }
+
+        return &mut self.external_packages[index - self.members.len()];
+    }
+
+    /// Indices of the packages the package at `index` directly depends on.
+    /// Optional ones are left out unless a feature turned them on.
+    pub fn dependency_indices(&self, index: usize) -> Result<Vec<usize>, WorkspaceError> {
+        let package = self.package(index);
+
+        let mut indices = vec![];
+
+        for name in &package.dependencies {
+            let optional = package
+                .dependency_manifests
+                .get(name)
+                .is_some_and(|it| it.optional);
+
+            if optional && !package.enabled_dependencies.contains(name) {
+                continue;
+            }
+
+            if package.cargo_crates.contains_key(name) {
+                continue;
+            }
+
+            indices.push(self.dependency_index(name)?);
+        }
+
+        return Ok(indices);
+    }
+
+    /// The Rust crates the package at `index` directly depends on, like [`Workspace::dependency_indices`].
+    pub fn cargo_dependencies(&self, index: usize) -> Vec<&CargoCrate> {
+        let package = self.package(index);
+
+        return package
+            .dependencies
+            .iter()
+            .filter(|name| {
+                let optional = package
+                    .dependency_manifests
+                    .get(*name)
+                    .is_some_and(|it| it.optional);
+
+                return !optional || package.enabled_dependencies.contains(*name);
+            })
+            .filter_map(|name| package.cargo_crates.get(name))
+            .collect();
+    }
+
+    /// Index of the package a dependency called `name` points to.
+    pub fn dependency_index(&self, name: &StrReference) -> Result<usize, WorkspaceError> {
+        let Some(path) = self.dependencies.get(name).and_then(|it| it.path.as_ref()) else {
+            return Err(WorkspaceError::DependencyNotInWorkspace(
+                name.get().to_string(),
+                self.path.clone(),
+            ));
+        };
+
+        let Some(dependency) = self.packages().position(|it| &it.path == path) else {
+            return Err(WorkspaceError::PackageNotFound(path.clone()));
+        };
+
+        return Ok(dependency);
+    }
+
+    /// Include directories for compiling the package at `index`: its own public
+    /// and private ones, then the public ones of everything it depends on.
+    pub fn include_directories(&self, index: usize) -> Result<Vec<PathBuf>, WorkspaceError> {
+        let package = self.package(index);
+
+        let mut directories: Vec<PathBuf> = package
+            .public_include
+            .iter()
+            .chain(package.private_include.iter())
+            .chain(package.build.include.iter())
+            .cloned()
+            .collect();
+
+        let mut order = self.dependency_order(&[index])?;
+
+        // The package itself is always last.
+        order.pop();
+
+        for dependency in order.into_iter().rev() {
+            for directory in &self.package(dependency).public_include {
+                if !directories.contains(directory) {
+                    directories.push(directory.clone());
+                }
+            }
+        }
+
+        return Ok(directories);
+    }
+
+    /// Returns the roots and everything they depend on, with every package
+    /// coming after all of its dependencies.
+    pub fn dependency_order(&self, roots: &[usize]) -> Result<Vec<usize>, WorkspaceError> {
+        let mut order = vec![];
+        let mut stack = vec![];
+
+        for root in roots {
+            self.visit_dependencies(*root, &mut stack, &mut order)?;
+        }
+
+        return Ok(order);
+    }
+
+    fn visit_dependencies(
+        &self,
+        index: usize,
+        stack: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), WorkspaceError> {
+        if order.contains(&index) {
+            return Ok(());
+        }
+
+        if let Some(start) = stack.iter().position(|it| *it == index) {
+            let cycle = stack[start..]
+                .iter()
+                .chain([&index])
+                .map(|it| self.package(*it).name.get().to_string())
+                .collect();
+
+            return Err(WorkspaceError::DependencyCycle(cycle));
+        }
+
+        stack.push(index);
+
+        for dependency in self.dependency_indices(index)? {
+            self.visit_dependencies(dependency, stack, order)?;
+        }
+
+        stack.pop();
+
+        order.push(index);
+
+        return Ok(());
+    }
+
+    pub fn member_index(&self, name: &str) -> Result<usize, WorkspaceError> {
+        return self
+            .members
+            .iter()
+            .position(|it| it.name.get().deref() == name)
+            .ok_or_else(|| WorkspaceError::CannotFindMember(name.to_string(), self.path.clone()));
+    }
+
+    /// Picks the members to build. In order of priority: every member if `all` is set,
+    /// the ones named in `names`, the member containing the CWD, then `default_members`.
+    pub fn select_members(
+        &self,
+        names: &[String],
+        all: bool,
+    ) -> Result<Vec<usize>, WorkspaceError> {
+        if all {
+            return Ok((0..self.members.len()).collect());
+        }
+
+        if !names.is_empty() {
+            return names.iter().map(|it| self.member_index(it)).collect();
+        }
+
+        if let Some(current_member) = self.current_member {
+            return Ok(vec![current_member]);
+        }
+
+        if !self.default_members.is_empty() {
+            return Ok(self.default_members.to_vec());
+        }
+
+        return Err(WorkspaceError::NoMemberSelected(self.path.clone()));
+    }
+
+    /// Loads the workspace for `target`, falling back to `build.target`, then the host.
+    /// Only the `[target.<...>]` sections matching it get loaded, so building for
+    /// another target means loading the workspace again.
+    pub fn load(path: PathBuf, target: Option<&Target>) -> Result<Option<Self>, WorkspaceError> {
+        let mut workspace = Self::find(path, target)?;
+
+        if let Some(workspace) = &mut workspace {
+            workspace.load_external_packages()?;
+            workspace.check_package_names()?;
+        }
+
+        return Ok(workspace);
+    }
+
+    /// Outputs are named after their package and share a directory, so two packages
+    /// with the same name would overwrite each other.
+    fn check_package_names(&self) -> Result<(), WorkspaceError> {
+        let mut paths: HashMap<&StrReference, &PathBuf> = HashMap::new();
+
+        for package in self.members.iter().chain(self.external_packages.iter()) {
+            if let Some(path) = paths.insert(&package.name, &package.path) {
+                return Err(WorkspaceError::DuplicatePackage(
+                    package.name.get().to_string(),
+                    path.clone(),
+                    package.path.clone(),
+                ));
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn find(path: PathBuf, target: Option<&Target>) -> Result<Option<Self>, WorkspaceError> {
+        // Relative paths can't be popped past their first component, so we'd
+        // never find a workspace above the CWD without this.
+        let path = path.canonicalize()?;
+
+        if let Some(mut workspace) = Self::find_first_workspace(path.clone(), target)? {
             let mut member = None;
 
             for dependency in &workspace.dependencies {
@@ -298,26 +919,6 @@ impl Workspace {
                 }
             }
 
-            if let Some(selected_project) = selected_project {
-                for (index, package) in workspace.members.iter().enumerate() {
-                    if &package.name.get().deref() == &selected_project.deref() {
-                        member = Some(index);
-                        break;
-                    }
-                }
-
-                if member.is_none() {
-                    return Err(WorkspaceError::CannotFindMember(
-                        selected_project,
-                        workspace.path,
-                    ));
-                }
-
-                workspace.current_member = member;
-
-                return Ok(Some(workspace));
-            }
-
             for (index, package) in workspace.members.iter().enumerate() {
                 if path.starts_with(&package.path) {
                     member = Some(index);
@@ -327,7 +928,7 @@ impl Workspace {
 
             if member.is_none()
                 && let Some(package) =
-                    Self::map_expected_package_to_none(Self::find_first_package(path))?
+                    Self::map_expected_package_to_none(Self::find_first_package(path, target))?
                 && package.path.starts_with(&workspace.path)
             {
                 return Err(WorkspaceError::PackageNotInWorkspace(
@@ -341,6 +942,6 @@ impl Workspace {
             return Ok(Some(workspace));
         }
 
-        return Self::find_first_package(path);
+        return Self::find_first_package(path, target);
     }
 }
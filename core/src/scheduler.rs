@@ -0,0 +1,216 @@
+use std::{
+    collections::VecDeque,
+    io,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    sync::{Condvar, Mutex},
+    thread,
+};
+
+pub type JobId = usize;
+
+type JobFn<'a> = Box<dyn FnOnce() -> io::Result<()> + Send + 'a>;
+
+struct Job<'a> {
+    run: Option<JobFn<'a>>,
+    dependents: Vec<JobId>,
+    remaining_dependencies: usize,
+}
+
+struct State<'a> {
+    jobs: Vec<Job<'a>>,
+    ready: VecDeque<JobId>,
+    running: usize,
+    finished: usize,
+    error: Option<io::Error>,
+}
+
+/// Runs a graph of jobs, where each job only starts after all of its dependencies
+/// finished. At most `jobs` of them run at the same time.
+///
+/// If a job fails or panics, nothing new gets started, and the first error is returned
+/// once everything that was already running is done.
+pub struct Scheduler<'a> {
+    jobs: Vec<Job<'a>>,
+}
+
+pub fn default_job_count() -> usize {
+    return thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1);
+}
+
+impl<'a> Default for Scheduler<'a> {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new() -> Self {
+        return Self { jobs: vec![] };
+    }
+
+    /// Dependencies must've been added before the job depending on them.
+    pub fn add<F: FnOnce() -> io::Result<()> + Send + 'a>(
+        &mut self,
+        dependencies: &[JobId],
+        run: F,
+    ) -> JobId {
+        let id = self.jobs.len();
+
+        for dependency in dependencies {
+            self.jobs[*dependency].dependents.push(id);
+        }
+
+        self.jobs.push(Job {
+            run: Some(Box::new(run)),
+            dependents: vec![],
+            remaining_dependencies: dependencies.len(),
+        });
+
+        return id;
+    }
+
+    pub fn run(self, jobs: usize) -> io::Result<()> {
+        let total = self.jobs.len();
+
+        let ready = self
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| job.remaining_dependencies == 0)
+            .map(|(id, _)| id)
+            .collect();
+
+        let state = Mutex::new(State {
+            jobs: self.jobs,
+            ready,
+            running: 0,
+            finished: 0,
+            error: None,
+        });
+
+        let condvar = Condvar::new();
+
+        thread::scope(|scope| {
+            for _ in 0..jobs.clamp(1, total.max(1)) {
+                scope.spawn(|| Self::worker(&state, &condvar, total));
+            }
+        });
+
+        let state = state.into_inner().unwrap();
+
+        if let Some(error) = state.error {
+            return Err(error);
+        }
+
+        return Ok(());
+    }
+
+    fn worker(state: &Mutex<State<'a>>, condvar: &Condvar, total: usize) {
+        let mut guard = state.lock().unwrap();
+
+        loop {
+            let done = guard.finished == total || (guard.error.is_some() && guard.running == 0);
+
+            if done {
+                condvar.notify_all();
+                return;
+            }
+
+            let next = if guard.error.is_none() {
+                guard.ready.pop_front()
+            } else {
+                None
+            };
+
+            let Some(id) = next else {
+                guard = condvar.wait(guard).unwrap();
+                continue;
+            };
+
+            let run = guard.jobs[id].run.take().unwrap();
+
+            guard.running += 1;
+
+            drop(guard);
+
+            // A panicking job counts as a failed one, otherwise it'd never stop running.
+            let result = panic::catch_unwind(AssertUnwindSafe(run)).unwrap_or_else(|payload| {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|it| it.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".into());
+
+                return Err(io::Error::other(format!("Job panicked: {message}")));
+            });
+
+            guard = state.lock().unwrap();
+
+            guard.running -= 1;
+            guard.finished += 1;
+
+            match result {
+                Ok(()) => {
+                    let dependents = std::mem::take(&mut guard.jobs[id].dependents);
+
+                    for dependent in dependents {
+                        let job = &mut guard.jobs[dependent];
+
+                        job.remaining_dependencies -= 1;
+
+                        if job.remaining_dependencies == 0 {
+                            guard.ready.push_back(dependent);
+                        }
+                    }
+                }
+                Err(e) => {
+                    if guard.error.is_none() {
+                        guard.error = Some(e);
+                    }
+                }
+            }
+
+            condvar.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn panicking_jobs_fail_the_run() {
+        let ran = AtomicUsize::new(0);
+
+        let mut scheduler = Scheduler::new();
+
+        let failing = scheduler.add(&[], || panic!("broken"));
+
+        scheduler.add(&[failing], || {
+            ran.fetch_add(1, Ordering::SeqCst);
+
+            return Ok(());
+        });
+
+        for _ in 0..4 {
+            scheduler.add(&[], || {
+                ran.fetch_add(1, Ordering::SeqCst);
+
+                return Ok(());
+            });
+        }
+
+        let error = scheduler.run(2).unwrap_err();
+
+        assert_eq!(error.to_string(), "Job panicked: broken");
+
+        // The job depending on the panicking one never starts.
+        assert!(ran.load(Ordering::SeqCst) <= 4);
+    }
+}
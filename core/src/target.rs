@@ -0,0 +1,455 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::DEFAULT_TARGET;
+
+/// The kind of object files a target uses, which decides most of its naming rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ObjectFormat {
+    Elf,
+    MachO,
+    Coff,
+    Wasm,
+}
+
+/// A parsed `<arch>-<vendor>-<os>[-<env>]` triple. The vendor can be left out,
+/// like clang allows, in which case it's `unknown`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Target {
+    pub arch: String,
+    pub vendor: String,
+    pub os: String,
+    pub env: Option<String>,
+    pub object_format: ObjectFormat,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TargetError {
+    #[error("Invalid target triple {0:?}: {1}.")]
+    Invalid(String, String),
+}
+
+/// Architectures are matched by prefix, so `armv7` and `riscv64gc` work too.
+const ARCHES: &[&str] = &[
+    "x86_64",
+    "i386",
+    "i486",
+    "i586",
+    "i686",
+    "aarch64",
+    "arm64",
+    "arm",
+    "thumb",
+    "riscv32",
+    "riscv64",
+    "wasm32",
+    "wasm64",
+    "mips",
+    "powerpc",
+    "ppc",
+    "s390x",
+    "loongarch64",
+    "sparc",
+    "avr",
+    "msp430",
+    "xtensa",
+];
+
+/// Operating systems are matched by prefix as well, for versioned ones like `macosx14.0`.
+const OSES: &[&str] = &[
+    "linux",
+    "windows",
+    "mingw32",
+    "darwin",
+    "macos",
+    "ios",
+    "tvos",
+    "watchos",
+    "visionos",
+    "freebsd",
+    "netbsd",
+    "openbsd",
+    "dragonfly",
+    "android",
+    "fuchsia",
+    "haiku",
+    "solaris",
+    "illumos",
+    "wasi",
+    "emscripten",
+    "uefi",
+    "elf",
+    "none",
+    "unknown",
+];
+
+fn is_os(value: &str) -> bool {
+    return OSES.iter().any(|it| value.starts_with(it));
+}
+
+impl Target {
+    pub fn parse(triple: &str) -> Result<Self, TargetError> {
+        let invalid = |reason: &str| TargetError::Invalid(triple.to_string(), reason.to_string());
+
+        let parts: Vec<&str> = triple.split('-').collect();
+
+        if parts.iter().any(|it| {
+            it.is_empty()
+                || !it
+                    .chars()
+                    .all(|it| it.is_ascii_alphanumeric() || it == '_' || it == '.')
+        }) {
+            return Err(invalid("expected dash separated parts"));
+        }
+
+        let (arch, vendor, os, env) = match parts.as_slice() {
+            [arch, os] => (*arch, "unknown", *os, None),
+            // Nothing calls itself `unknown` on purpose, so that one's a vendor.
+            [arch, os, env] if is_os(os) && *os != "unknown" => (*arch, "unknown", *os, Some(*env)),
+            [arch, vendor, os] => (*arch, *vendor, *os, None),
+            [arch, vendor, os, env] => (*arch, *vendor, *os, Some(*env)),
+            _ => return Err(invalid("expected <arch>-<vendor>-<os>[-<env>]")),
+        };
+
+        if !ARCHES.iter().any(|it| arch.starts_with(it)) {
+            return Err(invalid(&format!("unknown architecture {arch:?}")));
+        }
+
+        if !is_os(os) {
+            return Err(invalid(&format!("unknown operating system {os:?}")));
+        }
+
+        let object_format = if arch.starts_with("wasm") {
+            ObjectFormat::Wasm
+        } else if vendor == "apple"
+            || ["darwin", "macos", "ios", "tvos", "watchos", "visionos"]
+                .iter()
+                .any(|it| os.starts_with(it))
+        {
+            ObjectFormat::MachO
+        } else if ["windows", "mingw32", "uefi"].contains(&os) {
+            ObjectFormat::Coff
+        } else {
+            ObjectFormat::Elf
+        };
+
+        return Ok(Self {
+            arch: arch.to_string(),
+            vendor: vendor.to_string(),
+            os: os.to_string(),
+            env: env.map(str::to_string),
+            object_format,
+        });
+    }
+
+    /// The target doggo itself was built for.
+    pub fn host() -> Self {
+        return Self::parse(DEFAULT_TARGET).unwrap_or_else(|_| Self {
+            arch: std::env::consts::ARCH.to_string(),
+            vendor: "unknown".to_string(),
+            os: std::env::consts::OS.to_string(),
+            env: None,
+            object_format: ObjectFormat::Elf,
+        });
+    }
+
+    pub fn is_msvc(&self) -> bool {
+        return self.env.as_deref() == Some("msvc");
+    }
+
+    pub fn is_windows(&self) -> bool {
+        return self.os == "windows" || self.os == "mingw32";
+    }
+
+    pub fn object_suffix(&self) -> &'static str {
+        return if self.is_msvc() { "obj" } else { "o" };
+    }
+
+    pub fn static_suffix(&self) -> &'static str {
+        return if self.is_msvc() { "lib" } else { "a" };
+    }
+
+    pub fn dynamic_suffix(&self) -> &'static str {
+        return match self.object_format {
+            ObjectFormat::Coff => "dll",
+            ObjectFormat::MachO => "dylib",
+            ObjectFormat::Elf | ObjectFormat::Wasm => "so",
+        };
+    }
+
+    pub fn library_prefix(&self) -> &'static str {
+        return if self.is_msvc() { "" } else { "lib" };
+    }
+
+    /// The architecture as `cfg(target_arch)` names it, so `i686` is `x86` and `armv7` is `arm`.
+    pub fn cfg_arch(&self) -> &str {
+        let arch = self.arch.as_str();
+
+        return match arch {
+            "i386" | "i486" | "i586" | "i686" => "x86",
+            "arm64" => "aarch64",
+            _ if arch.starts_with("arm") || arch.starts_with("thumb") => "arm",
+            _ if arch.starts_with("riscv64") => "riscv64",
+            _ if arch.starts_with("riscv32") => "riscv32",
+            _ if arch.starts_with("powerpc64") || arch.starts_with("ppc64") => "powerpc64",
+            _ if arch.starts_with("powerpc") || arch.starts_with("ppc") => "powerpc",
+            _ if arch.starts_with("mips64") => "mips64",
+            _ if arch.starts_with("mips") => "mips",
+            _ => arch,
+        };
+    }
+
+    /// The OS as `cfg(target_os)` names it, without versions, and `none` for bare metal.
+    /// `<arch>-linux-android` is `android`, like Rust has it.
+    pub fn cfg_os(&self) -> &str {
+        let os = OSES
+            .iter()
+            .filter(|it| self.os.starts_with(*it))
+            .max_by_key(|it| it.len())
+            .copied()
+            .unwrap_or(&self.os);
+
+        return match os {
+            "linux"
+                if self
+                    .env
+                    .as_deref()
+                    .is_some_and(|it| it.starts_with("android")) =>
+            {
+                "android"
+            }
+            "darwin" => "macos",
+            "mingw32" => "windows",
+            "elf" | "unknown" => "none",
+            _ => os,
+        };
+    }
+
+    /// `unix`, `windows` or `wasm`, like `cfg(target_family)`. Bare metal has none.
+    pub fn family(&self) -> Option<&'static str> {
+        if self.object_format == ObjectFormat::Wasm {
+            return Some("wasm");
+        }
+
+        return match self.cfg_os() {
+            "windows" => Some("windows"),
+            "none" | "uefi" => None,
+            _ => Some("unix"),
+        };
+    }
+
+    pub fn pointer_width(&self) -> u32 {
+        let arch = self.cfg_arch();
+
+        if arch == "avr" || arch == "msp430" {
+            return 16;
+        }
+
+        return if arch.contains("64") || arch == "s390x" {
+            64
+        } else {
+            32
+        };
+    }
+
+    /// The same target, spelled the way rustc wants it. Triples rustc already knows
+    /// come out unchanged.
+    pub fn rust_triple(&self) -> String {
+        let arch = match self.arch.as_str() {
+            "arm64" => "aarch64",
+            arch => arch,
+        };
+
+        // Rust leaves the vendor out of these, but not out of other bare metal targets.
+        let no_vendor = ["arm", "thumb", "msp430", "avr"]
+            .iter()
+            .any(|it| arch.starts_with(it))
+            && self.vendor == "unknown";
+
+        return match (self.cfg_os(), self.env.as_deref()) {
+            ("macos", _) => format!("{arch}-apple-darwin"),
+            ("ios" | "tvos" | "watchos" | "visionos", env) => {
+                let os = self.cfg_os();
+
+                match env {
+                    Some(env) => format!("{arch}-apple-{os}-{env}"),
+                    None => format!("{arch}-apple-{os}"),
+                }
+            }
+            ("windows", Some("msvc")) => format!("{arch}-pc-windows-msvc"),
+            ("windows", _) => format!("{arch}-pc-windows-gnu"),
+            ("android", Some(env)) => format!("{arch}-linux-{env}"),
+            ("linux", env) => format!("{arch}-unknown-linux-{}", env.unwrap_or("gnu")),
+            // Like `wasm32-unknown-unknown`.
+            ("none", env) if self.os == "unknown" => match env {
+                Some(env) => format!("{arch}-{}-unknown-{env}", self.vendor),
+                None => format!("{arch}-{}-unknown", self.vendor),
+            },
+            ("none", Some(env)) if no_vendor => format!("{arch}-none-{env}"),
+            ("none", None) if no_vendor => format!("{arch}-none"),
+            ("none", Some(env)) => format!("{arch}-{}-none-{env}", self.vendor),
+            ("none", None) => format!("{arch}-{}-none", self.vendor),
+            (os, Some(env)) => format!("{arch}-{}-{os}-{env}", self.vendor),
+            (os, None) => format!("{arch}-{}-{os}", self.vendor),
+        };
+    }
+
+    /// ELF and Mach-O executables don't get an extension.
+    pub fn executable_suffix(&self) -> Option<&'static str> {
+        return match self.object_format {
+            ObjectFormat::Coff => Some("exe"),
+            ObjectFormat::Wasm => Some("wasm"),
+            ObjectFormat::Elf | ObjectFormat::MachO => None,
+        };
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}-{}", self.arch, self.vendor, self.os)?;
+
+        if let Some(env) = &self.env {
+            write!(f, "-{env}")?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl FromStr for Target {
+    type Err = TargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return Self::parse(s);
+    }
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let triple = String::deserialize(deserializer)?;
+
+        return Self::parse(&triple).map_err(serde::de::Error::custom);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(triple: &str) -> Target {
+        return Target::parse(triple).unwrap();
+    }
+
+    #[test]
+    fn parses_triples() {
+        for (triple, expected) in [
+            ("x86_64-pc-linux-gnu", "x86_64-pc-linux-gnu"),
+            ("x86_64-linux-gnu", "x86_64-unknown-linux-gnu"),
+            ("aarch64-unknown-linux-musl", "aarch64-unknown-linux-musl"),
+            ("x86_64-pc-windows-msvc", "x86_64-pc-windows-msvc"),
+            ("x86_64-w64-mingw32", "x86_64-w64-mingw32"),
+            ("arm64-apple-macosx14.0", "arm64-apple-macosx14.0"),
+            ("aarch64-apple-ios-sim", "aarch64-apple-ios-sim"),
+            ("thumbv7em-none-eabihf", "thumbv7em-unknown-none-eabihf"),
+            ("riscv64gc-unknown-none-elf", "riscv64gc-unknown-none-elf"),
+            ("wasm32-unknown-unknown", "wasm32-unknown-unknown"),
+            ("wasm32-wasi", "wasm32-unknown-wasi"),
+            ("x86_64-unknown-freebsd", "x86_64-unknown-freebsd"),
+        ] {
+            let parsed = target(triple);
+
+            assert_eq!(parsed.to_string(), expected, "{triple}");
+            assert_eq!(target(expected), parsed, "{triple}");
+        }
+
+        let mac = target("arm64-apple-macosx14.0");
+
+        assert_eq!(mac.object_format, ObjectFormat::MachO);
+        assert_eq!(mac.cfg_arch(), "aarch64");
+        assert_eq!(mac.cfg_os(), "macos");
+
+        let android = target("aarch64-linux-android");
+
+        assert_eq!(android.cfg_os(), "android");
+        assert_eq!(android.family(), Some("unix"));
+
+        assert_eq!(
+            target("thumbv7em-none-eabihf").env.as_deref(),
+            Some("eabihf")
+        );
+        assert_eq!(
+            target("x86_64-pc-windows-msvc").object_format,
+            ObjectFormat::Coff
+        );
+        assert_eq!(
+            target("wasm32-unknown-unknown").object_format,
+            ObjectFormat::Wasm
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_triples() {
+        for triple in [
+            "",
+            "x86_64",
+            "x86_64--linux",
+            "z80-unknown-linux",
+            "x86_64-unknown-templeos",
+            "x86_64-pc-linux-gnu-extra",
+            "x86_64 pc-linux",
+        ] {
+            assert!(Target::parse(triple).is_err(), "{triple:?}");
+        }
+    }
+
+    #[test]
+    fn rust_triples_pass_through() {
+        for triple in [
+            "x86_64-unknown-linux-gnu",
+            "aarch64-unknown-linux-musl",
+            "armv7-unknown-linux-gnueabihf",
+            "aarch64-linux-android",
+            "x86_64-pc-windows-msvc",
+            "x86_64-pc-windows-gnu",
+            "aarch64-apple-darwin",
+            "aarch64-apple-ios",
+            "aarch64-apple-ios-sim",
+            "x86_64-unknown-freebsd",
+            "x86_64-unknown-uefi",
+            "aarch64-unknown-none",
+            "x86_64-unknown-none",
+            "riscv64gc-unknown-none-elf",
+            "riscv32imac-unknown-none-elf",
+            "thumbv7em-none-eabihf",
+            "thumbv6m-none-eabi",
+            "msp430-none-elf",
+            "wasm32-unknown-unknown",
+            "wasm32-unknown-emscripten",
+        ] {
+            assert_eq!(target(triple).rust_triple(), triple);
+
+            // Parsing what rustc calls it gives back the same target.
+            assert_eq!(target(&target(triple).rust_triple()), target(triple));
+        }
+    }
+
+    #[test]
+    fn rust_triples_from_other_spellings() {
+        for (triple, expected) in [
+            ("x86_64-pc-linux-gnu", "x86_64-unknown-linux-gnu"),
+            ("x86_64-linux", "x86_64-unknown-linux-gnu"),
+            ("arm64-apple-macosx14.0", "aarch64-apple-darwin"),
+            ("x86_64-apple-darwin23.1.0", "x86_64-apple-darwin"),
+            ("aarch64-apple-ios17.0", "aarch64-apple-ios"),
+            ("x86_64-w64-mingw32", "x86_64-pc-windows-gnu"),
+            ("x86_64-pc-windows", "x86_64-pc-windows-gnu"),
+            ("thumbv7em-unknown-none-eabihf", "thumbv7em-none-eabihf"),
+        ] {
+            assert_eq!(target(triple).rust_triple(), expected, "{triple}");
+        }
+    }
+}
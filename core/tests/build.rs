@@ -0,0 +1,425 @@
+#![allow(clippy::needless_return)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
+};
+
+use doggo_core::{
+    build::{BuildConfig, build},
+    features::FeatureSelection,
+    fingerprint::Fingerprint,
+    mock::{MockCall, MockCompilerBackend},
+    plan::BuildPlan,
+    project::Workspace,
+    target::Target,
+};
+
+/// A workspace in a temporary directory: `app`, an executable, depends on `mathlib`, a
+/// static library, which depends on `base`, a dynamic library.
+struct TestWorkspace {
+    path: PathBuf,
+}
+
+impl TestWorkspace {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("doggo-test-{}-{name}", process::id()));
+
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        let workspace = Self { path };
+
+        workspace.write(
+            "Doggo.toml",
+            "[workspace]\nmembers = [\"app\", \"mathlib\", \"base\"]\n",
+        );
+
+        workspace.write(
+            "app/Doggo.toml",
+            "[package]\nname = \"app\"\noutput = \"executable\"\n\n[dependencies]\nmathlib = { path = \"../mathlib\" }\n",
+        );
+        workspace.write("app/src/main.c", "int main(void) { return 0; }\n");
+
+        workspace.write(
+            "mathlib/Doggo.toml",
+            "[package]\nname = \"mathlib\"\noutput = \"static_library\"\n\n[build]\nlink = [\"m\"]\n\n[dependencies]\nbase = { path = \"../base\" }\n",
+        );
+        workspace.write(
+            "mathlib/src/add.c",
+            "int add(int a, int b) { return a + b; }\n",
+        );
+        workspace.write(
+            "mathlib/src/mul.c",
+            "int mul(int a, int b) { return a * b; }\n",
+        );
+
+        workspace.write(
+            "base/Doggo.toml",
+            "[package]\nname = \"base\"\noutput = \"dynamic_library\"\n",
+        );
+        workspace.write("base/src/base.c", "int base(void) { return 1; }\n");
+
+        return workspace;
+    }
+
+    /// Files start out a minute old, so anything built afterwards is newer than them.
+    fn write(&self, path: &str, contents: &str) {
+        let path = self.path.join(path);
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+
+        set_modified(&path, ago(60));
+    }
+
+    /// Changes a file, so it's newer than everything built so far, without relying
+    /// on the clock moving on between the two.
+    fn edit(&self, path: &str, contents: &str) {
+        // All at once, so none of them end up newer than ones they were built from.
+        age_all(&self.path.join(".doggo"), ago(30));
+
+        let path = self.path.join(path);
+
+        fs::write(&path, contents).unwrap();
+
+        set_modified(&path, ago(10));
+    }
+
+    fn load(&self, target: &str) -> (Workspace, BuildConfig, Vec<usize>) {
+        let target = Target::parse(target).unwrap();
+
+        let mut workspace = Workspace::load(self.path.clone(), Some(&target))
+            .unwrap()
+            .unwrap();
+
+        let roots = workspace.select_members(&["app".into()], false).unwrap();
+
+        workspace
+            .resolve_features(&roots, &FeatureSelection::default())
+            .unwrap();
+
+        let config = BuildConfig::new(&workspace, workspace.profile("dev").unwrap(), target);
+
+        return (workspace, config, roots);
+    }
+
+    fn build(&self, compiler: &MockCompilerBackend) -> std::io::Result<BuildPlan> {
+        let (workspace, config, roots) = self.load("x86_64-pc-linux-gnu");
+
+        return build(&workspace, compiler, &config, &roots, 4).map_err(std::io::Error::other);
+    }
+}
+
+impl Drop for TestWorkspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn ago(seconds: u64) -> SystemTime {
+    return SystemTime::now() - Duration::from_secs(seconds);
+}
+
+fn set_modified(path: &Path, time: SystemTime) {
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(time)
+        .unwrap();
+}
+
+fn age_all(directory: &Path, time: SystemTime) {
+    for entry in fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            age_all(&path, time);
+        } else {
+            set_modified(&path, time);
+        }
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    return Path::new(path).file_name().unwrap().to_str().unwrap();
+}
+
+/// The file names of what the calls produced, in order.
+fn outputs(calls: &[MockCall]) -> Vec<&str> {
+    return calls.iter().map(|it| file_name(it.output())).collect();
+}
+
+fn position(calls: &[MockCall], name: &str) -> usize {
+    return outputs(calls).iter().position(|it| *it == name).unwrap();
+}
+
+#[test]
+fn names_outputs_after_the_target() {
+    let test = TestWorkspace::new("naming");
+    let compiler = MockCompilerBackend::new();
+
+    for (target, expected) in [
+        ("x86_64-pc-linux-gnu", ["app", "libmathlib.a", "libbase.so"]),
+        (
+            "x86_64-pc-windows-msvc",
+            ["app.exe", "mathlib.lib", "base.dll"],
+        ),
+        (
+            "aarch64-apple-darwin",
+            ["app", "libmathlib.a", "libbase.dylib"],
+        ),
+    ] {
+        let (workspace, config, roots) = test.load(target);
+
+        let plan = BuildPlan::new(&workspace, &compiler, &config, &roots).unwrap();
+
+        for (name, expected) in ["app", "mathlib", "base"].into_iter().zip(expected) {
+            let index = workspace.member_index(name).unwrap();
+
+            assert_eq!(
+                plan.outputs[&index].file_name().unwrap(),
+                expected,
+                "{target}"
+            );
+
+            assert!(plan.outputs[&index].starts_with(&config.build_directory));
+        }
+
+        let object = plan
+            .actions
+            .iter()
+            .find(|it| it.argv.iter().any(|arg| arg.ends_with("add.c")))
+            .unwrap();
+
+        let suffix = if target.ends_with("msvc") {
+            "add.obj"
+        } else {
+            "add.o"
+        };
+
+        assert_eq!(file_name(object.output()), suffix);
+    }
+
+    assert!(
+        compiler.calls().is_empty(),
+        "planning shouldn't build anything"
+    );
+}
+
+#[test]
+fn builds_dependencies_first() {
+    let test = TestWorkspace::new("ordering");
+    let compiler = MockCompilerBackend::new();
+
+    test.build(&compiler).unwrap();
+
+    let calls = compiler.calls();
+
+    assert_eq!(calls.len(), 7);
+
+    for object in ["add.o", "mul.o"] {
+        assert!(position(&calls, object) < position(&calls, "libmathlib.a"));
+    }
+
+    assert!(position(&calls, "base.o") < position(&calls, "libbase.so"));
+    assert!(position(&calls, "main.o") < position(&calls, "app"));
+    assert!(position(&calls, "libmathlib.a") < position(&calls, "app"));
+    assert!(position(&calls, "libbase.so") < position(&calls, "app"));
+
+    let Some(MockCall::Link {
+        static_libs,
+        dynamic_libs,
+        dynamic_library,
+        ..
+    }) = calls.iter().find(|it| file_name(it.output()) == "app")
+    else {
+        panic!("app wasn't linked");
+    };
+
+    // Static libraries bring their dependencies and system libraries along.
+    assert_eq!(static_libs.len(), 1);
+    assert_eq!(file_name(&static_libs[0]), "libmathlib.a");
+    assert_eq!(dynamic_libs, &["m", "base"]);
+    assert!(!dynamic_library);
+}
+
+#[test]
+fn skips_outputs_that_are_up_to_date() {
+    let test = TestWorkspace::new("up-to-date");
+    let compiler = MockCompilerBackend::new();
+
+    test.build(&compiler).unwrap();
+    compiler.take_calls();
+
+    let plan = test.build(&compiler).unwrap();
+
+    assert_eq!(compiler.calls(), vec![]);
+    assert_eq!(plan.pending().unwrap(), vec![]);
+}
+
+#[test]
+fn loading_again_keeps_outputs_up_to_date() {
+    let test = TestWorkspace::new("reload");
+    let compiler = MockCompilerBackend::new();
+
+    // Enough dependencies that a different order each load would show up.
+    for name in ["alpha", "beta", "gamma"] {
+        test.write(
+            &format!("{name}/Doggo.toml"),
+            &format!("[package]\nname = \"{name}\"\noutput = \"static_library\"\n"),
+        );
+        test.write(
+            &format!("{name}/src/{name}.c"),
+            &format!("int {name}(void) {{ return 0; }}\n"),
+        );
+    }
+
+    test.write(
+        "app/Doggo.toml",
+        "[package]\nname = \"app\"\noutput = \"executable\"\n\n[dependencies]\n\
+         mathlib = { path = \"../mathlib\" }\nalpha = { path = \"../alpha\" }\n\
+         gamma = { path = \"../gamma\" }\nbeta = { path = \"../beta\" }\n",
+    );
+
+    test.build(&compiler).unwrap();
+
+    for _ in 0..8 {
+        let (workspace, config, roots) = test.load("x86_64-pc-linux-gnu");
+        let plan = BuildPlan::new(&workspace, &compiler, &config, &roots).unwrap();
+
+        assert_eq!(plan.pending().unwrap(), vec![]);
+    }
+
+    compiler.take_calls();
+
+    test.build(&compiler).unwrap();
+
+    assert_eq!(compiler.calls(), vec![]);
+}
+
+#[test]
+fn fingerprints_match_after_reloading() {
+    let test = TestWorkspace::new("fingerprints");
+    let compiler = MockCompilerBackend::new();
+
+    let built = test.build(&compiler).unwrap();
+
+    // A fresh load from disk, like the next `doggo build` would do.
+    let (workspace, config, roots) = test.load("x86_64-pc-linux-gnu");
+    let plan = BuildPlan::new(&workspace, &compiler, &config, &roots).unwrap();
+
+    assert_eq!(plan.actions.len(), built.actions.len());
+
+    for action in &plan.actions {
+        let written = fs::read_to_string(Fingerprint::path_for(action.output())).unwrap();
+
+        assert!(
+            written.contains(&action.argv.join("\n")),
+            "{}",
+            action.output()
+        );
+        assert!(
+            action.fingerprint().matches(action.output()).unwrap(),
+            "{}",
+            action.output()
+        );
+    }
+}
+
+#[test]
+fn rebuilds_what_depends_on_a_changed_source() {
+    let test = TestWorkspace::new("changed-source");
+    let compiler = MockCompilerBackend::new();
+
+    test.build(&compiler).unwrap();
+    compiler.take_calls();
+
+    test.edit(
+        "mathlib/src/add.c",
+        "int add(int a, int b) { return b + a; }\n",
+    );
+
+    let (workspace, config, roots) = test.load("x86_64-pc-linux-gnu");
+    let plan = BuildPlan::new(&workspace, &compiler, &config, &roots).unwrap();
+
+    let pending = plan
+        .pending()
+        .unwrap()
+        .into_iter()
+        .map(|it| file_name(plan.actions[it].output()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(pending, ["add.o", "libmathlib.a", "app"]);
+
+    test.build(&compiler).unwrap();
+
+    assert_eq!(outputs(&compiler.calls()), ["add.o", "libmathlib.a", "app"]);
+}
+
+#[test]
+fn rebuilds_when_the_command_changes() {
+    let test = TestWorkspace::new("changed-command");
+
+    test.build(&MockCompilerBackend::new()).unwrap();
+
+    let compiler = MockCompilerBackend::with_identity("upgraded mock");
+
+    test.build(&compiler).unwrap();
+
+    assert_eq!(compiler.take_calls().len(), 7);
+
+    test.edit(
+        "base/Doggo.toml",
+        "[package]\nname = \"base\"\noutput = \"dynamic_library\"\n\n[build]\ndefines = [\"BASE_VERSION=2\"]\n",
+    );
+
+    test.build(&compiler).unwrap();
+
+    let calls = compiler.calls();
+
+    assert_eq!(outputs(&calls), ["base.o", "libbase.so", "app"]);
+
+    let MockCall::Compile { defines, .. } = &calls[0] else {
+        panic!("expected a compile, got {:?}", calls[0]);
+    };
+
+    assert!(defines.contains(&"BASE_VERSION=2".to_string()));
+}
+
+#[test]
+fn stops_after_a_failure() {
+    let test = TestWorkspace::new("failure");
+    let compiler = MockCompilerBackend::new();
+
+    compiler.fail_on("mul.c");
+
+    assert!(test.build(&compiler).is_err());
+
+    let calls = compiler.calls();
+
+    assert!(outputs(&calls).contains(&"mul.o"));
+    assert!(!outputs(&calls).contains(&"libmathlib.a"));
+    assert!(!outputs(&calls).contains(&"app"));
+
+    let compiler = MockCompilerBackend::new();
+
+    test.build(&compiler).unwrap();
+
+    let rebuilt = compiler.calls();
+
+    // What got built before the failure doesn't get built again.
+    for call in &calls {
+        let name = file_name(call.output());
+
+        assert_eq!(outputs(&rebuilt).contains(&name), name == "mul.o", "{name}");
+    }
+
+    for name in ["mul.o", "libmathlib.a", "app"] {
+        assert!(outputs(&rebuilt).contains(&name), "{name}");
+    }
+}
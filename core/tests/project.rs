@@ -0,0 +1,216 @@
+#![allow(clippy::needless_return)]
+
+use std::{collections::BTreeSet, fs, path::PathBuf, process};
+
+use doggo_core::{
+    features::FeatureSelection,
+    project::{Workspace, WorkspaceError},
+    target::Target,
+};
+
+/// A temporary directory, removed again when dropped.
+struct TestPackage {
+    path: PathBuf,
+}
+
+impl TestPackage {
+    fn empty(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("doggo-project-{}-{name}", process::id()));
+
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        return Self { path };
+    }
+
+    /// A package with the given manifest, next to two libraries, `unix` and `windows`.
+    fn new(name: &str, manifest: &str) -> Self {
+        let package = Self::empty(name);
+
+        package.write("Doggo.toml", manifest);
+        package.write("src/main.c", "int main(void) { return 0; }\n");
+
+        for platform in ["unix", "windows"] {
+            package.write(
+                &format!("{platform}/Doggo.toml"),
+                &format!("[package]\nname = \"{platform}\"\noutput = \"static_library\"\n"),
+            );
+        }
+
+        return package;
+    }
+
+    /// A workspace with an `app` member depending on `lib`, through `[dependencies]`
+    /// of the workspace given as `dependency`.
+    fn workspace(name: &str, dependency: &str, app_dependency: &str) -> Self {
+        let workspace = Self::empty(name);
+
+        workspace.write(
+            "Doggo.toml",
+            &format!("[workspace]\nmembers = [\"app\"]\n\n[dependencies]\nlib = {dependency}\n"),
+        );
+
+        workspace.write(
+            "app/Doggo.toml",
+            &format!(
+                "[package]\nname = \"app\"\noutput = \"executable\"\n\n[dependencies]\nlib = {app_dependency}\n"
+            ),
+        );
+        workspace.write("app/src/main.c", "int main(void) { return 0; }\n");
+
+        workspace.write(
+            "lib/Doggo.toml",
+            "[package]\nname = \"lib\"\noutput = \"static_library\"\n\n\
+             [features]\ndefault = [\"fast\"]\nfast = []\nextra = []\nmore = []\n",
+        );
+        workspace.write("lib/src/lib.c", "int lib(void) { return 0; }\n");
+
+        return workspace;
+    }
+
+    /// The features `lib` ends up with when building `app`.
+    fn lib_features(&self) -> BTreeSet<String> {
+        let mut workspace = self.load("x86_64-pc-linux-gnu").unwrap();
+
+        let roots = workspace.select_members(&["app".into()], false).unwrap();
+
+        workspace
+            .resolve_features(&roots, &FeatureSelection::default())
+            .unwrap();
+
+        let index = workspace.dependency_indices(roots[0]).unwrap()[0];
+
+        return workspace.package(index).enabled_features.clone();
+    }
+
+    fn write(&self, path: &str, contents: &str) {
+        let path = self.path.join(path);
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+    }
+
+    fn load(&self, target: &str) -> Result<Workspace, WorkspaceError> {
+        let target = Target::parse(target).unwrap();
+
+        return Ok(Workspace::load(self.path.clone(), Some(&target))?.unwrap());
+    }
+}
+
+impl Drop for TestPackage {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The names of the packages the package being built depends on.
+fn dependency_names(workspace: &Workspace) -> Vec<String> {
+    return workspace
+        .dependency_indices(0)
+        .unwrap()
+        .into_iter()
+        .map(|it| workspace.package(it).name.get().to_string())
+        .collect();
+}
+
+#[test]
+fn target_tables_can_point_a_dependency_at_different_paths() {
+    let package = TestPackage::new(
+        "per-target",
+        "[package]\nname = \"app\"\noutput = \"executable\"\n\n\
+         [target.'cfg(unix)'.dependencies]\nplatform = { path = \"unix\" }\n\n\
+         [target.'cfg(windows)'.dependencies]\nplatform = { path = \"windows\" }\n",
+    );
+
+    let linux = package.load("x86_64-pc-linux-gnu").unwrap();
+
+    assert_eq!(dependency_names(&linux), ["unix"]);
+
+    let windows = package.load("x86_64-pc-windows-msvc").unwrap();
+
+    assert_eq!(dependency_names(&windows), ["windows"]);
+
+    let bare_metal = package.load("thumbv7em-none-eabihf").unwrap();
+
+    assert_eq!(dependency_names(&bare_metal), Vec::<String>::new());
+    assert!(bare_metal.external_packages.is_empty());
+}
+
+#[test]
+fn conflicting_dependency_paths_are_an_error() {
+    let package = TestPackage::new(
+        "conflicting",
+        "[package]\nname = \"app\"\noutput = \"executable\"\n\n\
+         [dependencies]\nplatform = { path = \"windows\" }\n\n\
+         [target.'cfg(unix)'.dependencies]\nplatform = { path = \"unix\" }\n",
+    );
+
+    assert!(matches!(
+        package.load("x86_64-pc-linux-gnu"),
+        Err(WorkspaceError::ConflictingDependency(name, _)) if name == "platform"
+    ));
+
+    // The table that doesn't match isn't looked at.
+    let windows = package.load("x86_64-pc-windows-msvc").unwrap();
+
+    assert_eq!(dependency_names(&windows), ["windows"]);
+}
+
+#[test]
+fn path_dependencies_inherit_workspace_features() {
+    let workspace = TestPackage::workspace(
+        "inherit-features",
+        "{ path = \"lib\", features = [\"extra\"] }",
+        "{ workspace = true, features = [\"more\"] }",
+    );
+
+    assert_eq!(
+        workspace.lib_features(),
+        BTreeSet::from([
+            "default".into(),
+            "extra".into(),
+            "fast".into(),
+            "more".into()
+        ])
+    );
+}
+
+#[test]
+fn path_dependencies_inherit_workspace_default_features() {
+    let workspace = TestPackage::workspace(
+        "inherit-default-features",
+        "{ path = \"lib\", default_features = false }",
+        "{ workspace = true, features = [\"more\"] }",
+    );
+
+    assert_eq!(workspace.lib_features(), BTreeSet::from(["more".into()]));
+
+    // Either one turning them off is enough, like with Cargo crates.
+    let workspace = TestPackage::workspace(
+        "member-default-features",
+        "{ path = \"lib\" }",
+        "{ workspace = true, default_features = false }",
+    );
+
+    assert_eq!(workspace.lib_features(), BTreeSet::new());
+}
+
+#[test]
+fn packages_with_the_same_name_are_an_error() {
+    let package = TestPackage::new(
+        "same-name",
+        "[package]\nname = \"app\"\noutput = \"executable\"\n\n\
+         [dependencies]\nunix = { path = \"unix\" }\nalso_unix = { path = \"windows\" }\n",
+    );
+
+    package.write(
+        "windows/Doggo.toml",
+        "[package]\nname = \"unix\"\noutput = \"static_library\"\n",
+    );
+
+    assert!(matches!(
+        package.load("x86_64-pc-linux-gnu"),
+        Err(WorkspaceError::DuplicatePackage(name, ..)) if name == "unix"
+    ));
+}